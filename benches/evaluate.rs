@@ -0,0 +1,96 @@
+//! Evaluation throughput of the interpreters, run with `cargo bench --bench evaluate`
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use evofunc::{Instruction, Program};
+
+const ROWS: usize = 4_000_000;
+
+/// Best wall time of a few runs of `f`
+fn bench(name: &str, mut f: impl FnMut() -> f32) -> Duration {
+    let best = (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    println!("{name:<32} {:>8.1} ms", best.as_secs_f64() * 1e3);
+    best
+}
+
+fn main() {
+    // (c_0 * v_0 + c_1 * v_1 - v_0 * v_1 / (v_0 + c_2)) * 0.5
+    let program = Program::create(&[
+        Instruction::Var(0),
+        Instruction::Const(0),
+        Instruction::Mul,
+        Instruction::Var(1),
+        Instruction::Const(1),
+        Instruction::Mul,
+        Instruction::Add,
+        Instruction::Var(0),
+        Instruction::Var(1),
+        Instruction::Mul,
+        Instruction::Var(0),
+        Instruction::Const(2),
+        Instruction::Add,
+        Instruction::Div,
+        Instruction::Sub,
+        Instruction::Lit(0.5),
+        Instruction::Mul,
+    ]);
+    let consts = [1.5f32, -0.25, 3.0];
+    let x = (0..ROWS).map(|i| i as f32 * 1e-6).collect::<Vec<_>>();
+    let y = (0..ROWS)
+        .map(|i| (i % 1000) as f32 - 500.0)
+        .collect::<Vec<_>>();
+
+    let run = bench("Program::run", || {
+        (0..ROWS)
+            .map(|i| {
+                let mut stack = program.run(&consts, &[x[i], y[i]]).unwrap();
+                stack.pop().unwrap()
+            })
+            .sum()
+    });
+    let compiled = program.compile::<f32>().unwrap();
+    let compiled = bench("CompiledProgram", || {
+        (0..ROWS)
+            .map(|i| compiled.evaluate(&consts, &[x[i], y[i]]).unwrap())
+            .sum()
+    });
+    println!(
+        "compiled speedup {:.2}x",
+        run.as_secs_f64() / compiled.as_secs_f64()
+    );
+
+    for (name, program) in [
+        ("arithmetic", program.clone()),
+        // sin(v_0) * v_1 + ... with a libm call per row
+        ("with sin", {
+            let mut program = program.clone();
+            program.push_inst(Instruction::Var(0));
+            program.push_inst(Instruction::Sin);
+            program.push_inst(Instruction::Var(1));
+            program.push_inst(Instruction::Mul);
+            program.push_inst(Instruction::Add);
+            program
+        }),
+    ] {
+        let batch = bench(&format!("evaluate_batch {name}"), || {
+            program.evaluate_batch(&consts, &[&x, &y]).unwrap()[ROWS - 1]
+        });
+        let simd = bench(&format!("evaluate_batch_simd {name}"), || {
+            program.evaluate_batch_simd(&consts, &[&x, &y]).unwrap()[ROWS - 1]
+        });
+        println!(
+            "simd speedup {name} {:.2}x",
+            batch.as_secs_f64() / simd.as_secs_f64()
+        );
+    }
+}
@@ -19,7 +19,8 @@ fn main() {
 
     let distance_to_pi = move |prog: &Program| {
         prog.evaluate_to_result_and_remaining_stack(&CONSTS, &[])
-            .map(|(r, rest)| -(r - std::f32::consts::PI).abs())
+            .ok()
+            .map(|(r, _rest)| -(r - std::f32::consts::PI).abs())
     };
 
     let mut mcts = MCTS::with_max_program_length(&iset, 64, distance_to_pi);
@@ -54,21 +55,10 @@ fn main() {
             let prog = mcts.make_best_program();
             let result = prog.evaluate_to_result(&CONSTS, &[]);
             let unformatted = mcts.make_best_program().render();
-            let prog = mcts.make_best_program().render_pretty(&CONSTS)/*.map(|p| {
-                let pycmd = format!("from sympy.parsing.sympy_parser import parse_expr; from sympy import E, simplify; print(simplify(parse_expr(\"{p}\", local_dict={{\"e\":E}}, evaluate=False)))");
-                let out = std::process::Command::new("python").arg("-c").arg(pycmd).output();
-                match out {
-                    Err(e) => {
-                        format!("Unoptimized: {}", p)
-                    }
-                    Ok(out)=> {
-                        if out.stderr.len() > 0 {
-                            eprintln!("Error formatting: {}", String::from_utf8_lossy(&out.stderr))
-                        }
-                        String::from_utf8_lossy(&out.stdout).trim().to_string()
-                    },
-                }                
-            })*/;
+            let prog = mcts
+                .make_best_program()
+                .simplify(&CONSTS)
+                .and_then(|p| p.render_pretty(&CONSTS));
             eprintln!(
                 "\nNew highscore {} with program {} [{:?}] => {:?} ",
                 high_score.map(|v| v.to_string()).unwrap_or("/".to_string()),
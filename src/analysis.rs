@@ -0,0 +1,198 @@
+use std::fmt::Display;
+
+use crate::instructions::{Instruction, Program, STACKSIZE};
+
+/// Result of the static stack-effect analysis of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackReport {
+    /// Stack depth after each instruction
+    pub depths: Vec<usize>,
+    /// Number of values left on the stack after the program finished
+    pub final_depth: usize,
+    pub max_depth: usize,
+    /// Highest `Const` index used, `None` if the program uses no constants
+    pub max_const_index: Option<u8>,
+    /// Highest `Var` index used, `None` if the program uses no variables
+    pub max_var_index: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The instruction at `index` needs more values than there are on the stack
+    StackUnderflow { index: usize, instruction: Instruction },
+    /// The instruction at `index` pushes more than `STACKSIZE` values
+    StackOverflow { index: usize },
+    /// The program does not leave a result on the stack
+    EmptyResult,
+    /// The instruction at `index` reads a constant that does not exist
+    ConstOutOfRange { index: usize, const_index: u8 },
+    /// The instruction at `index` reads a variable that does not exist
+    VarOutOfRange { index: usize, var_index: u8 },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::StackUnderflow { index, instruction } => {
+                write!(f, "stack underflow at instruction {index} ({instruction:?})")
+            }
+            ValidationError::StackOverflow { index } => write!(
+                f,
+                "stack overflow at instruction {index}, the stack holds at most {STACKSIZE} values"
+            ),
+            ValidationError::EmptyResult => write!(f, "program leaves no result on the stack"),
+            ValidationError::ConstOutOfRange { index, const_index } => write!(
+                f,
+                "instruction {index} reads Const({const_index}), which is out of range"
+            ),
+            ValidationError::VarOutOfRange { index, var_index } => write!(
+                f,
+                "instruction {index} reads Var({var_index}), which is out of range"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Instruction {
+    /// Number of values the instruction pops from and pushes onto the stack
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Instruction::Dup => (1, 2),
+            Instruction::Swap => (2, 2),
+            Instruction::Drop => (1, 0),
+            Instruction::Over => (2, 3),
+            Instruction::Rot => (3, 3),
+            _ => (self.operator_arity().unwrap_or(0), 1),
+        }
+    }
+}
+
+impl Program {
+    /// Computes the stack depth after every instruction without running the program
+    pub fn analyze(&self) -> Result<StackReport, ValidationError> {
+        let mut depth = 0;
+        let mut report = StackReport {
+            depths: Vec::with_capacity(self.len()),
+            final_depth: 0,
+            max_depth: 0,
+            max_const_index: None,
+            max_var_index: None,
+        };
+        for (index, inst) in self.instructions.iter().enumerate() {
+            let (pops, pushes) = inst.stack_effect();
+            if depth < pops {
+                return Err(ValidationError::StackUnderflow {
+                    index,
+                    instruction: *inst,
+                });
+            }
+            depth = depth - pops + pushes;
+            if depth > STACKSIZE {
+                return Err(ValidationError::StackOverflow { index });
+            }
+            match inst {
+                Instruction::Const(idx) => {
+                    report.max_const_index = report.max_const_index.max(Some(*idx))
+                }
+                Instruction::Var(idx) => report.max_var_index = report.max_var_index.max(Some(*idx)),
+                _ => {}
+            }
+            report.max_depth = report.max_depth.max(depth);
+            report.depths.push(depth);
+        }
+        report.final_depth = depth;
+        Ok(report)
+    }
+
+    /// Checks that the program can be run with `const_count` constants and `var_count` variables
+    /// and leaves a result on the stack
+    pub fn validate(
+        &self,
+        const_count: usize,
+        var_count: usize,
+    ) -> Result<StackReport, ValidationError> {
+        let report = self.analyze()?;
+        for (index, inst) in self.instructions.iter().enumerate() {
+            match inst {
+                Instruction::Const(idx) if *idx as usize >= const_count => {
+                    return Err(ValidationError::ConstOutOfRange {
+                        index,
+                        const_index: *idx,
+                    })
+                }
+                Instruction::Var(idx) if *idx as usize >= var_count => {
+                    return Err(ValidationError::VarOutOfRange {
+                        index,
+                        var_index: *idx,
+                    })
+                }
+                _ => {}
+            }
+        }
+        if report.final_depth == 0 {
+            return Err(ValidationError::EmptyResult);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_stack_effects() {
+        let program = Program::create(&[
+            Instruction::Var(1),
+            Instruction::Dup,
+            Instruction::Const(2),
+            Instruction::Rot,
+            Instruction::Mul,
+            Instruction::Over,
+            Instruction::Drop,
+        ]);
+        let report = program.analyze().unwrap();
+        assert_eq!(report.depths, vec![1, 2, 3, 3, 2, 3, 2]);
+        assert_eq!(report.final_depth, 2);
+        assert_eq!(report.max_depth, 3);
+        assert_eq!(report.max_const_index, Some(2));
+        assert_eq!(report.max_var_index, Some(1));
+
+        assert_eq!(
+            program.validate(2, 2),
+            Err(ValidationError::ConstOutOfRange {
+                index: 2,
+                const_index: 2
+            })
+        );
+        assert_eq!(
+            program.validate(3, 1),
+            Err(ValidationError::VarOutOfRange {
+                index: 0,
+                var_index: 1
+            })
+        );
+        assert!(program.validate(3, 2).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_programs() {
+        let underflow = Program::create(&[Instruction::Var(0), Instruction::Swap]);
+        assert_eq!(
+            underflow.analyze(),
+            Err(ValidationError::StackUnderflow {
+                index: 1,
+                instruction: Instruction::Swap
+            })
+        );
+        let overflow = Program::create(&vec![Instruction::Lit(1.0); STACKSIZE + 1]);
+        assert_eq!(
+            overflow.analyze(),
+            Err(ValidationError::StackOverflow { index: STACKSIZE })
+        );
+        let empty = Program::create(&[Instruction::Var(0), Instruction::Drop]);
+        assert_eq!(empty.validate(0, 1), Err(ValidationError::EmptyResult));
+    }
+}
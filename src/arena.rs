@@ -29,7 +29,7 @@ impl<T> Arena<T>{
 
     pub fn allocate(&mut self, item: T) -> Ap<T>{
         self.items.push(item);
-        ArenaPointer(self.items.len() - 1, PhantomData::default())
+        ArenaPointer(self.items.len() - 1, PhantomData)
     }
 }
 
@@ -37,7 +37,7 @@ pub struct ArenaPointer<T>(usize, PhantomData<T>);
 
 impl<T> Clone for ArenaPointer<T>{
     fn clone(&self) -> Self {
-        Self(self.0.clone(), PhantomData::default())
+        *self
     }
 }
 
@@ -0,0 +1,256 @@
+use crate::{
+    instructions::{EvalError, Instruction, Program, STACKSIZE},
+    scalar::Scalar,
+    semantics::Semantics,
+};
+
+/// Stack of columns that recycles the buffers of popped columns
+struct ColumnStack<T> {
+    rows: usize,
+    columns: Vec<Vec<T>>,
+    free: Vec<Vec<T>>,
+}
+
+impl<T: Scalar> ColumnStack<T> {
+    fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            columns: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn allocate(&mut self) -> Vec<T> {
+        self.free
+            .pop()
+            .unwrap_or_else(|| vec![T::ZERO; self.rows])
+    }
+
+    fn push_value(&mut self, value: T) {
+        let mut column = self.allocate();
+        column.fill(value);
+        self.columns.push(column);
+    }
+
+    fn push_copy(&mut self, values: &[T]) {
+        let mut column = self.allocate();
+        column.copy_from_slice(values);
+        self.columns.push(column);
+    }
+
+    fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    fn push(&mut self, column: Vec<T>) {
+        self.columns.push(column);
+    }
+
+    fn pop(&mut self) -> Option<Vec<T>> {
+        self.columns.pop()
+    }
+
+    fn recycle(&mut self, column: Vec<T>) {
+        self.free.push(column);
+    }
+}
+
+fn check<T: Scalar>(semantics: Semantics, column: &[T]) -> Option<()> {
+    if semantics == Semantics::Checked && !column.iter().all(|v| v.is_finite()) {
+        None
+    } else {
+        Some(())
+    }
+}
+
+impl Program {
+    /// Evaluates the program on every row of a column-major dataset, where `columns[i]` holds the
+    /// values of `Var(i)`.
+    ///
+    /// Each instruction is interpreted once for a whole column instead of once per row.
+    /// All columns need to have the same length, a dataset without columns is treated as a single row.
+    pub fn evaluate_batch<T: Scalar>(&self, consts: &[T], columns: &[&[T]]) -> Result<Vec<T>, EvalError> {
+        self.evaluate_batch_with(Semantics::default(), consts, columns)
+    }
+
+    /// Batch evaluation with the given operator semantics, with `Semantics::Checked` the whole
+    /// batch fails if an operator produces a non-finite value in any row
+    pub fn evaluate_batch_with<T: Scalar>(
+        &self,
+        semantics: Semantics,
+        consts: &[T],
+        columns: &[&[T]],
+    ) -> Result<Vec<T>, EvalError> {
+        let rows = columns.first().map(|c| c.len()).unwrap_or(1);
+        if let Some(column) = columns.iter().position(|c| c.len() != rows) {
+            return Err(EvalError::ColumnLengthMismatch { column });
+        }
+        let mut stack = ColumnStack::new(rows);
+        let mut args = Vec::new();
+        for (index, inst) in self.instructions.iter().enumerate() {
+            let underflow = EvalError::StackUnderflow { index };
+            let non_finite = EvalError::NonFinite { index };
+            match inst {
+                Instruction::Add
+                | Instruction::Sub
+                | Instruction::Mul
+                | Instruction::Div
+                | Instruction::Pow
+                | Instruction::Lt
+                | Instruction::Gt => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let mut a = stack.pop().ok_or(underflow)?;
+                    for (a, b) in a.iter_mut().zip(b.iter()) {
+                        *a = inst.apply_binary(semantics, *a, *b);
+                    }
+                    check(semantics, &a).ok_or(non_finite)?;
+                    stack.recycle(b);
+                    stack.push(a);
+                }
+                Instruction::Exp
+                | Instruction::Log
+                | Instruction::Sin
+                | Instruction::Cos
+                | Instruction::Tan
+                | Instruction::Sqrt
+                | Instruction::Abs
+                | Instruction::Neg => {
+                    let mut v = stack.pop().ok_or(underflow)?;
+                    for v in v.iter_mut() {
+                        *v = inst.apply_unary(semantics, *v);
+                    }
+                    check(semantics, &v).ok_or(non_finite)?;
+                    stack.push(v);
+                }
+                Instruction::Select => {
+                    let f = stack.pop().ok_or(underflow)?;
+                    let t = stack.pop().ok_or(underflow)?;
+                    let mut c = stack.pop().ok_or(underflow)?;
+                    for ((c, t), f) in c.iter_mut().zip(t.iter()).zip(f.iter()) {
+                        *c = Instruction::apply_select(*c, *t, *f);
+                    }
+                    stack.recycle(t);
+                    stack.recycle(f);
+                    stack.push(c);
+                }
+                Instruction::Dup => {
+                    let v = stack.pop().ok_or(underflow)?;
+                    stack.push_copy(&v);
+                    stack.push(v);
+                }
+                Instruction::Swap => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Instruction::Drop => {
+                    let v = stack.pop().ok_or(underflow)?;
+                    stack.recycle(v);
+                }
+                Instruction::Over => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push_copy(&a);
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Instruction::Rot => {
+                    let c = stack.pop().ok_or(underflow)?;
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push(b);
+                    stack.push(c);
+                    stack.push(a);
+                }
+                Instruction::Const(idx) => {
+                    let v = consts.get(*idx as usize).ok_or(EvalError::ConstOutOfRange {
+                        index,
+                        const_index: *idx,
+                    })?;
+                    stack.push_value(*v);
+                }
+                Instruction::Var(idx) => {
+                    let column = columns.get(*idx as usize).ok_or(EvalError::VarOutOfRange {
+                        index,
+                        var_index: *idx,
+                    })?;
+                    stack.push_copy(column);
+                }
+                Instruction::Lit(v) => {
+                    stack.push_value(T::from_f32(*v));
+                }
+                Instruction::Custom(op) => {
+                    let arity = op.arity();
+                    let inputs = (0..arity)
+                        .map(|_| stack.pop().ok_or(underflow))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut result = stack.allocate();
+                    for (row, r) in result.iter_mut().enumerate() {
+                        args.clear();
+                        args.extend(inputs.iter().rev().map(|column| column[row]));
+                        *r = T::eval_operator(*op, &args);
+                    }
+                    check(semantics, &result).ok_or(non_finite)?;
+                    for column in inputs {
+                        stack.recycle(column);
+                    }
+                    stack.push(result);
+                }
+            }
+            if stack.len() > STACKSIZE {
+                return Err(EvalError::StackOverflow { index });
+            }
+        }
+        stack.pop().ok_or(EvalError::EmptyResult)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalError, Instruction, Program};
+
+    #[test]
+    fn test_batch_matches_row_evaluation() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Dup,
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Const(0),
+            Instruction::Div,
+            Instruction::Sin,
+            Instruction::Swap,
+            Instruction::Sub,
+            Instruction::Var(0),
+            Instruction::Lit(0.0),
+            Instruction::Gt,
+            Instruction::Over,
+            Instruction::Const(1),
+            Instruction::Select,
+            Instruction::Add,
+        ]);
+        let consts: [f32; 2] = [2.0, -1.5];
+        let x = [0.0, 1.0, -2.0, 3.5, 0.25];
+        let y = [4.0, -3.0, 0.5, 1.0, 0.0];
+        let batch = program.evaluate_batch(&consts, &[&x, &y]).unwrap();
+        assert_eq!(batch.len(), x.len());
+        for (row, result) in batch.iter().enumerate() {
+            let expected = program
+                .evaluate_to_result(&consts, &[x[row], y[row]])
+                .unwrap();
+            assert_eq!(result.to_bits(), expected.to_bits());
+        }
+        assert_eq!(
+            program.evaluate_batch(&consts, &[&x, &y[1..]]),
+            Err(EvalError::ColumnLengthMismatch { column: 1 })
+        );
+        assert_eq!(
+            program.evaluate_batch(&consts, &[&x]),
+            Err(EvalError::VarOutOfRange {
+                index: 3,
+                var_index: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    expr::Expr,
+    instructions::{Instruction, Program},
+};
+
+/// Program in canonical form, two programs computing the same expression up to the order of
+/// commutative operands and the grouping of associative operators compare and hash equal
+#[derive(Clone, Debug)]
+pub struct CanonicalProgram {
+    program: Program,
+    hash: u64,
+}
+
+impl CanonicalProgram {
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn into_program(self) -> Program {
+        self.program
+    }
+
+    /// Hash of the canonical form that is stable across runs, platforms and crate versions
+    pub fn semantic_hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl PartialEq for CanonicalProgram {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.program == other.program
+    }
+}
+
+impl Eq for CanonicalProgram {}
+
+impl Hash for CanonicalProgram {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+fn rank(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Var(_) => 0,
+        Expr::Const(_) => 1,
+        Expr::Lit(_) => 2,
+        Expr::Op(..) => 3,
+    }
+}
+
+/// Orders operators by opcode name, and custom operators by their name and arity. The names are
+/// spelled out so the canonical form does not depend on the declaration order or `Debug` output
+/// of `Instruction`.
+fn opcode(inst: Instruction) -> (&'static str, &'static str, usize) {
+    let name = match inst {
+        Instruction::Add => "Add",
+        Instruction::Sub => "Sub",
+        Instruction::Mul => "Mul",
+        Instruction::Div => "Div",
+        Instruction::Exp => "Exp",
+        Instruction::Log => "Log",
+        Instruction::Sin => "Sin",
+        Instruction::Cos => "Cos",
+        Instruction::Tan => "Tan",
+        Instruction::Pow => "Pow",
+        Instruction::Sqrt => "Sqrt",
+        Instruction::Abs => "Abs",
+        Instruction::Neg => "Neg",
+        Instruction::Lt => "Lt",
+        Instruction::Gt => "Gt",
+        Instruction::Select => "Select",
+        Instruction::Custom(op) => return ("Custom", op.name(), op.arity()),
+        _ => unreachable!("{inst:?} is not an operator"),
+    };
+    (name, "", 0)
+}
+
+/// Total order on trees used to sort commutative operands
+fn compare(a: &Expr, b: &Expr) -> Ordering {
+    match (a, b) {
+        (Expr::Var(a), Expr::Var(b)) | (Expr::Const(a), Expr::Const(b)) => a.cmp(b),
+        (Expr::Lit(a), Expr::Lit(b)) => a.total_cmp(b),
+        (Expr::Op(a, a_operands), Expr::Op(b, b_operands)) => opcode(*a)
+            .cmp(&opcode(*b))
+            .then_with(|| a_operands.len().cmp(&b_operands.len()))
+            .then_with(|| {
+                a_operands
+                    .iter()
+                    .zip(b_operands)
+                    .map(|(a, b)| compare(a, b))
+                    .find(|o| o.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            }),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Collects the operands of a chain of the associative operator `inst`
+fn flatten(inst: Instruction, expr: Expr, operands: &mut Vec<Expr>) {
+    match expr {
+        Expr::Op(op, children) if op == inst => {
+            for child in children {
+                flatten(inst, child, operands);
+            }
+        }
+        expr => operands.push(expr),
+    }
+}
+
+fn canonicalize(expr: Expr) -> Expr {
+    let Expr::Op(inst, operands) = expr else {
+        return expr;
+    };
+    let mut operands = operands.into_iter().map(canonicalize).collect::<Vec<_>>();
+    match inst {
+        Instruction::Add | Instruction::Mul => {
+            let mut flat = Vec::new();
+            for operand in operands {
+                flatten(inst, operand, &mut flat);
+            }
+            flat.sort_by(compare);
+            let mut flat = flat.into_iter();
+            let first = flat.next().unwrap();
+            flat.fold(first, |acc, operand| Expr::Op(inst, vec![acc, operand]))
+        }
+        // `a > b` is the same comparison as `b < a`
+        Instruction::Gt => {
+            operands.reverse();
+            Expr::Op(Instruction::Lt, operands)
+        }
+        _ => Expr::Op(inst, operands),
+    }
+}
+
+/// 64 bit FNV-1a, unlike `std`'s hashers its output is specified and never changes
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Program {
+    /// Rewrites the value the program leaves on top of its stack into a canonical program:
+    /// operands of `Add` and `Mul` chains are flattened and sorted, `Gt` is expressed as `Lt`, and
+    /// stack manipulation is resolved. Returns `None` if the program does not leave a result.
+    ///
+    /// Reassociating floating point additions and multiplications can change the rounding of the
+    /// result, so canonical programs are equivalent up to rounding.
+    pub fn canonicalize(&self) -> Option<CanonicalProgram> {
+        let program = canonicalize(Expr::from_program(self)?).to_program_unchecked();
+        let hash = fnv1a(program.render().as_bytes());
+        Some(CanonicalProgram { program, hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{Instruction, Program};
+
+    #[test]
+    fn test_equivalent_programs_are_equal() {
+        // (v_0 + c_0) * v_1
+        let a = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Add,
+            Instruction::Var(1),
+            Instruction::Mul,
+        ]);
+        // v_1 * (c_0 + v_0)
+        let b = Program::create(&[
+            Instruction::Var(1),
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Add,
+            Instruction::Mul,
+        ]);
+        // v_0 + v_1 * c_0
+        let c = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Const(0),
+            Instruction::Mul,
+            Instruction::Add,
+        ]);
+        assert_ne!(a, b);
+        assert_eq!(a.canonicalize(), b.canonicalize());
+        assert_ne!(a.canonicalize(), c.canonicalize());
+        assert_eq!(
+            a.canonicalize().unwrap().semantic_hash(),
+            b.canonicalize().unwrap().semantic_hash()
+        );
+        let unique = [&a, &b, &c]
+            .iter()
+            .map(|p| p.canonicalize().unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[test]
+    fn test_associative_chains_and_comparisons() {
+        // (v_2 * v_0) * v_1 and v_1 * (v_0 * v_2) with the stack rearranged
+        let a = Program::create(&[
+            Instruction::Var(2),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Mul,
+        ]);
+        let b = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(2),
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Swap,
+            Instruction::Mul,
+        ]);
+        let canonical = a.canonicalize().unwrap();
+        assert_eq!(Some(&canonical), b.canonicalize().as_ref());
+        assert_eq!(canonical.program().render(), "Var(0) Var(1) Mul Var(2) Mul");
+
+        let lt = Program::create(&[Instruction::Var(0), Instruction::Var(1), Instruction::Lt]);
+        let gt = Program::create(&[Instruction::Var(1), Instruction::Var(0), Instruction::Gt]);
+        assert_eq!(lt.canonicalize(), gt.canonicalize());
+        assert_eq!(Program::new().canonicalize(), None);
+    }
+
+    #[test]
+    fn test_semantic_hash_is_stable() {
+        // sin(v_0) * 2.5 + (c_0 + v_1)
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Lit(2.5),
+            Instruction::Mul,
+            Instruction::Const(0),
+            Instruction::Var(1),
+            Instruction::Add,
+            Instruction::Add,
+        ]);
+        let canonical = program.canonicalize().unwrap();
+        assert_eq!(
+            canonical.program().render(),
+            "Var(1) Const(0) Add Lit(2.5) Var(0) Sin Mul Add"
+        );
+        // Changing this value breaks hashes users have stored, only do so deliberately
+        assert_eq!(canonical.semantic_hash(), 0x37bd_31a6_dae5_f441);
+    }
+}
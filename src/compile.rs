@@ -0,0 +1,372 @@
+use crate::{
+    instructions::{EvalError, Instruction, Program, STACKSIZE},
+    scalar::Scalar,
+    semantics::Semantics,
+};
+
+/// Computes one operator into its slot, reading its operands from the inputs and earlier slots
+type Step<T> = Box<dyn Fn(&[T], &[T], &mut [T]) -> Result<(), EvalError> + Send + Sync>;
+
+/// Slots up to which `CompiledProgram::evaluate` keeps its intermediate values on the stack
+const INLINE_SLOTS: usize = 32;
+
+/// Where a value is read from. Leaves are read straight from the inputs instead of being copied
+/// into a slot first.
+#[derive(Clone, Copy)]
+enum Operand<T> {
+    Slot(usize),
+    Const(usize),
+    Var(usize),
+    Lit(T),
+}
+
+impl<T: Scalar> Operand<T> {
+    #[inline(always)]
+    fn get(self, consts: &[T], vars: &[T], slots: &[T]) -> T {
+        match self {
+            Operand::Slot(i) => slots[i],
+            Operand::Const(i) => consts[i],
+            Operand::Var(i) => vars[i],
+            Operand::Lit(v) => v,
+        }
+    }
+}
+
+/// A program compiled into a sequence of specialized closures, created by `Program::compile`
+pub struct CompiledProgram<T = f32> {
+    steps: Box<[Step<T>]>,
+    /// Index of the instruction of every step
+    step_indices: Box<[usize]>,
+    /// Index and instruction of every `Const` and `Var`, including dropped ones
+    reads: Box<[(usize, Instruction)]>,
+    /// Number of consts and vars the program reads without going out of range
+    const_len: usize,
+    var_len: usize,
+    slots: usize,
+    result: Operand<T>,
+}
+
+impl<T: Scalar> CompiledProgram<T> {
+    /// Evaluates the compiled program, giving the same result as `Program::evaluate_to_result_with`
+    /// for the semantics it was compiled with
+    pub fn evaluate(&self, consts: &[T], vars: &[T]) -> Result<T, EvalError> {
+        if self.slots <= INLINE_SLOTS {
+            let mut slots = [T::ZERO; INLINE_SLOTS];
+            self.evaluate_in(&mut slots[..self.slots], consts, vars)
+        } else {
+            self.evaluate_with_buffer(&mut Vec::new(), consts, vars)
+        }
+    }
+
+    /// Same as `CompiledProgram::evaluate`, but keeps the intermediate values in `buffer` so
+    /// repeated calls do not allocate
+    pub fn evaluate_with_buffer(
+        &self,
+        buffer: &mut Vec<T>,
+        consts: &[T],
+        vars: &[T],
+    ) -> Result<T, EvalError> {
+        if buffer.len() < self.slots {
+            buffer.resize(self.slots, T::ZERO);
+        }
+        self.evaluate_in(&mut buffer[..self.slots], consts, vars)
+    }
+
+    fn evaluate_in(&self, slots: &mut [T], consts: &[T], vars: &[T]) -> Result<T, EvalError> {
+        if consts.len() < self.const_len || vars.len() < self.var_len {
+            // Like `Program::run`, errors of the operators before the first read out of range
+            // take precedence
+            let (index, error) = self.first_read_out_of_range(consts, vars);
+            let steps = self.step_indices.partition_point(|i| *i < index);
+            for step in &self.steps[..steps] {
+                step(consts, vars, slots)?;
+            }
+            return Err(error);
+        }
+        for step in self.steps.iter() {
+            step(consts, vars, slots)?;
+        }
+        Ok(self.result.get(consts, vars, slots))
+    }
+
+    fn first_read_out_of_range(&self, consts: &[T], vars: &[T]) -> (usize, EvalError) {
+        self.reads
+            .iter()
+            .find_map(|(index, inst)| {
+                let index = *index;
+                match *inst {
+                    Instruction::Const(idx) if idx as usize >= consts.len() => Some((
+                        index,
+                        EvalError::ConstOutOfRange {
+                            index,
+                            const_index: idx,
+                        },
+                    )),
+                    Instruction::Var(idx) if idx as usize >= vars.len() => Some((
+                        index,
+                        EvalError::VarOutOfRange {
+                            index,
+                            var_index: idx,
+                        },
+                    )),
+                    _ => None,
+                }
+            })
+            .unwrap()
+    }
+}
+
+// Every operator gets its own closure with the instruction and semantics as constants, so
+// evaluation neither dispatches on them nor checks finiteness unless the semantics require it
+macro_rules! specialize {
+    ($inst:expr, $semantics:expr, [$($op:ident),*], $make:ident) => {
+        match ($inst, $semantics) {
+            $(
+                (Instruction::$op, Semantics::Ieee) => $make!(Instruction::$op, Semantics::Ieee),
+                (Instruction::$op, Semantics::Protected) => {
+                    $make!(Instruction::$op, Semantics::Protected)
+                }
+                (Instruction::$op, Semantics::Checked) => {
+                    $make!(Instruction::$op, Semantics::Checked)
+                }
+            )*
+            _ => unreachable!(),
+        }
+    };
+}
+
+fn compile_operator<T: Scalar>(
+    index: usize,
+    inst: Instruction,
+    semantics: Semantics,
+    operands: Vec<Operand<T>>,
+    slot: usize,
+    args: usize,
+) -> Step<T> {
+    let non_finite = EvalError::NonFinite { index };
+    match inst {
+        Instruction::Select => {
+            let [c, t, f] = [operands[0], operands[1], operands[2]];
+            Box::new(move |consts, vars, slots| {
+                let c = c.get(consts, vars, slots);
+                let t = t.get(consts, vars, slots);
+                let f = f.get(consts, vars, slots);
+                slots[slot] = Instruction::apply_select(c, t, f);
+                Ok(())
+            })
+        }
+        // The arguments are gathered in the slots reserved at `args` for this operator
+        Instruction::Custom(op) => Box::new(move |consts, vars, slots| {
+            for (i, operand) in operands.iter().enumerate() {
+                slots[args + i] = operand.get(consts, vars, slots);
+            }
+            let v = T::eval_operator(op, &slots[args..args + operands.len()]);
+            slots[slot] = semantics.check(v).ok_or(non_finite)?;
+            Ok(())
+        }),
+        _ if operands.len() == 2 => {
+            let [a, b] = [operands[0], operands[1]];
+            macro_rules! binary {
+                ($op:expr, $semantics:expr) => {
+                    Box::new(move |consts, vars, slots| {
+                        let a = a.get(consts, vars, slots);
+                        let b = b.get(consts, vars, slots);
+                        slots[slot] = $semantics
+                            .check($op.apply_binary($semantics, a, b))
+                            .ok_or(non_finite)?;
+                        Ok(())
+                    })
+                };
+            }
+            specialize!(inst, semantics, [Add, Sub, Mul, Div, Pow, Lt, Gt], binary)
+        }
+        _ => {
+            let a = operands[0];
+            macro_rules! unary {
+                ($op:expr, $semantics:expr) => {
+                    Box::new(move |consts, vars, slots| {
+                        let a = a.get(consts, vars, slots);
+                        slots[slot] = $semantics
+                            .check($op.apply_unary($semantics, a))
+                            .ok_or(non_finite)?;
+                        Ok(())
+                    })
+                };
+            }
+            specialize!(inst, semantics, [Exp, Log, Sin, Cos, Tan, Sqrt, Abs, Neg], unary)
+        }
+    }
+}
+
+impl Program {
+    /// Compiles the program into a sequence of closures that evaluates the value on top of the
+    /// stack without interpreting instructions. Fails if the program underflows its stack or
+    /// leaves no result, errors depending on the inputs are reported by `CompiledProgram::evaluate`.
+    pub fn compile<T: Scalar>(&self) -> Result<CompiledProgram<T>, EvalError> {
+        self.compile_with(Semantics::default())
+    }
+
+    pub fn compile_with<T: Scalar>(
+        &self,
+        semantics: Semantics,
+    ) -> Result<CompiledProgram<T>, EvalError> {
+        // Symbolically run the program on a stack of operands. Every operator gets its own slot
+        // and is computed in instruction order, so dropped values still report their errors like
+        // in `Program::run`, and stack manipulation shares slots instead of recomputing them.
+        let mut steps: Vec<Step<T>> = Vec::new();
+        let mut step_indices = Vec::new();
+        let mut reads = Vec::new();
+        let (mut const_len, mut var_len, mut slots) = (0, 0, 0);
+        let mut stack: Vec<Operand<T>> = Vec::new();
+        for (index, inst) in self.instructions.iter().copied().enumerate() {
+            let (pops, _) = inst.stack_effect();
+            if stack.len() < pops {
+                return Err(EvalError::StackUnderflow { index });
+            }
+            match inst {
+                Instruction::Const(idx) => {
+                    reads.push((index, inst));
+                    const_len = const_len.max(idx as usize + 1);
+                    stack.push(Operand::Const(idx as usize));
+                }
+                Instruction::Var(idx) => {
+                    reads.push((index, inst));
+                    var_len = var_len.max(idx as usize + 1);
+                    stack.push(Operand::Var(idx as usize));
+                }
+                Instruction::Lit(v) => stack.push(Operand::Lit(T::from_f32(v))),
+                Instruction::Dup => stack.push(stack[stack.len() - 1]),
+                Instruction::Swap => {
+                    let len = stack.len();
+                    stack.swap(len - 1, len - 2);
+                }
+                Instruction::Drop => {
+                    stack.pop();
+                }
+                Instruction::Over => stack.push(stack[stack.len() - 2]),
+                Instruction::Rot => {
+                    let len = stack.len();
+                    stack[len - 3..].rotate_left(1);
+                }
+                _ => {
+                    let operands = stack.split_off(stack.len() - pops);
+                    let slot = slots;
+                    let args = slot + 1;
+                    slots += 1;
+                    if let Instruction::Custom(_) = inst {
+                        slots += operands.len();
+                    }
+                    steps.push(compile_operator(
+                        index, inst, semantics, operands, slot, args,
+                    ));
+                    step_indices.push(index);
+                    stack.push(Operand::Slot(slot));
+                }
+            }
+            if stack.len() > STACKSIZE {
+                return Err(EvalError::StackOverflow { index });
+            }
+        }
+        let result = stack.pop().ok_or(EvalError::EmptyResult)?;
+        Ok(CompiledProgram {
+            steps: steps.into(),
+            step_indices: step_indices.into(),
+            reads: reads.into(),
+            const_len,
+            var_len,
+            slots,
+            result,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalError, Instruction, Program, Semantics};
+
+    #[test]
+    fn test_compiled_matches_interpreter() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Dup,
+            Instruction::Const(0),
+            Instruction::Div,
+            Instruction::Exp,
+            Instruction::Swap,
+            Instruction::Log,
+            Instruction::Var(1),
+            Instruction::Pow,
+            Instruction::Over,
+            Instruction::Gt,
+            Instruction::Var(1),
+            Instruction::Rot,
+            Instruction::Select,
+            Instruction::Lit(0.25),
+            Instruction::Sub,
+        ]);
+        let compiled = program.compile::<f32>().unwrap();
+        for consts in [[0.0f32], [3.0]] {
+            for vars in [[0.5f32, 2.0], [-1.0, 0.0], [4.0, -3.0]] {
+                let expected = program.evaluate_to_result(&consts, &vars).unwrap();
+                assert_eq!(
+                    compiled.evaluate(&consts, &vars).unwrap().to_bits(),
+                    expected.to_bits()
+                );
+            }
+        }
+        assert_eq!(
+            compiled.evaluate(&[], &[1.0, 2.0]),
+            program.evaluate_to_result(&[], &[1.0, 2.0])
+        );
+        assert!(matches!(
+            Program::create(&[Instruction::Add]).compile::<f32>(),
+            Err(EvalError::StackUnderflow { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_buffer_and_error_order() {
+        // v_0 + v_0 + ... with more intermediate values than fit on the stack
+        let mut program = Program::create(&[Instruction::Var(0)]);
+        for _ in 0..40 {
+            program.push_inst(Instruction::Var(0));
+            program.push_inst(Instruction::Add);
+        }
+        let compiled = program.compile::<f32>().unwrap();
+        let mut buffer = Vec::new();
+        for v in [1.0, 2.5] {
+            assert_eq!(compiled.evaluate(&[], &[v]), Ok(41.0 * v));
+            assert_eq!(
+                compiled.evaluate_with_buffer(&mut buffer, &[], &[v]),
+                Ok(41.0 * v)
+            );
+        }
+        assert_eq!(
+            compiled.evaluate_with_buffer(&mut buffer, &[], &[]),
+            Err(EvalError::VarOutOfRange {
+                index: 0,
+                var_index: 0
+            })
+        );
+
+        // ln(0) fails before the constant is read, the constant fails before ln(0) / v_1
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Log,
+            Instruction::Const(0),
+            Instruction::Var(1),
+            Instruction::Div,
+        ]);
+        let compiled = program.compile_with::<f32>(Semantics::Checked).unwrap();
+        for vars in [[0.0, 1.0], [1.0, 0.0]] {
+            assert_eq!(
+                compiled.evaluate(&[], &vars),
+                program.evaluate_to_result_with(Semantics::Checked, &[], &vars)
+            );
+        }
+        assert_eq!(
+            compiled.evaluate(&[], &[0.0, 1.0]),
+            Err(EvalError::NonFinite { index: 1 })
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+use crate::{
+    expr::{dag, DagNode},
+    instructions::{Instruction, Program},
+    semantics::Semantics,
+};
+
+fn symbol(inst: Instruction) -> String {
+    match inst {
+        Instruction::Add => "+".to_string(),
+        Instruction::Sub => "-".to_string(),
+        Instruction::Mul => "*".to_string(),
+        Instruction::Div => "/".to_string(),
+        Instruction::Pow => "**".to_string(),
+        Instruction::Lt => "<".to_string(),
+        Instruction::Gt => ">".to_string(),
+        Instruction::Neg => "neg".to_string(),
+        Instruction::Log => "ln".to_string(),
+        Instruction::Select => "if".to_string(),
+        Instruction::Custom(op) => op.name().to_string(),
+        inst => format!("{inst:?}").to_lowercase(),
+    }
+}
+
+/// Quotes `"` and `\` so custom operator names cannot end the label early
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Value of every node for the input `vars`, `None` for all nodes without `vars`
+fn values(nodes: &[DagNode], consts: &[f32], vars: Option<&[f32]>) -> Vec<Option<f32>> {
+    let semantics = Semantics::default();
+    let mut values: Vec<Option<f32>> = Vec::with_capacity(nodes.len());
+    for DagNode { inst, operands } in nodes {
+        let args = operands
+            .iter()
+            .map(|i| values[*i])
+            .collect::<Option<Vec<_>>>();
+        let value = vars.and_then(|vars| match *inst {
+            Instruction::Const(ci) => consts.get(ci as usize).copied(),
+            Instruction::Var(vi) => vars.get(vi as usize).copied(),
+            Instruction::Lit(v) => Some(v),
+            Instruction::Select => args.map(|a| Instruction::apply_select(a[0], a[1], a[2])),
+            Instruction::Custom(op) => args.map(|a| op.eval(&a)),
+            inst if operands.len() == 2 => args.map(|a| inst.apply_binary(semantics, a[0], a[1])),
+            inst => args.map(|a| inst.apply_unary(semantics, a[0])),
+        });
+        values.push(value);
+    }
+    values
+}
+
+/// Writes `node` and its operands unless they were written already, returns the node name
+fn write_node(
+    nodes: &[DagNode],
+    values: &[Option<f32>],
+    node: usize,
+    consts: &[f32],
+    names: &mut [Option<String>],
+    dotstr: &mut String,
+    next_id: &mut usize,
+) -> String {
+    if let Some(name) = &names[node] {
+        return name.clone();
+    }
+    let name = format!("n{next_id}");
+    *next_id += 1;
+    names[node] = Some(name.clone());
+    let DagNode { inst, operands } = &nodes[node];
+    for (i, operand) in operands.iter().enumerate() {
+        let child = write_node(nodes, values, *operand, consts, names, dotstr, next_id);
+        let edge = match inst {
+            Instruction::Select => ["cond", "then", "else"][i].to_string(),
+            _ if operands.len() > 1 => i.to_string(),
+            _ => String::new(),
+        };
+        let _ = writeln!(dotstr, "{name} -> {child} [label=\"{edge}\"]");
+    }
+    let (label, shape) = match inst {
+        Instruction::Const(ci) => match consts.get(*ci as usize) {
+            Some(v) => (format!("c_{ci} = {v}"), "box"),
+            None => (format!("c_{ci}"), "box"),
+        },
+        Instruction::Var(vi) => (format!("v_{vi}"), "box"),
+        Instruction::Lit(v) => (format!("{v}"), "box"),
+        inst => (symbol(*inst), "ellipse"),
+    };
+    let label = escape(&label);
+    let label = match values[node] {
+        Some(v) if !matches!(inst, Instruction::Lit(_) | Instruction::Const(_)) => {
+            format!("{label}\\n= {v}")
+        }
+        _ => label,
+    };
+    let _ = writeln!(dotstr, "{name} [label=\"{label}\", shape={shape}]");
+    name
+}
+
+impl Program {
+    /// Graphviz graph of the expression tree of the program's result, with operators as inner
+    /// nodes and constants, variables and literals as leaves. Values shared with `Dup` or `Over`
+    /// are a single node with an edge to every operator using them.
+    ///
+    /// If `vars` are given, every operator is annotated with its intermediate value for this input.
+    /// Returns `None` if the program does not leave a result on its stack.
+    pub fn to_dot(&self, consts: &[f32], vars: Option<&[f32]>) -> Option<String> {
+        let (nodes, result) = dag(self)?;
+        let values = values(&nodes, consts, vars);
+        let mut names = vec![None; nodes.len()];
+        let mut dotstr = String::from("digraph{\n");
+        write_node(
+            &nodes,
+            &values,
+            result,
+            consts,
+            &mut names,
+            &mut dotstr,
+            &mut 0,
+        );
+        dotstr.push_str("}\n");
+        Some(dotstr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Operator, Program};
+
+    struct Quoted;
+
+    impl Operator for Quoted {
+        fn name(&self) -> &str {
+            "say \"hi\\"
+        }
+
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn eval(&self, args: &[f32]) -> f32 {
+            args[0]
+        }
+    }
+
+    static QUOTED: Quoted = Quoted;
+
+    #[test]
+    fn test_to_dot() {
+        // (v_0 + c_0) * ln(v_0 + c_0)
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Add,
+            Instruction::Dup,
+            Instruction::Log,
+            Instruction::Mul,
+        ]);
+        assert_eq!(
+            program.to_dot(&[1.0], None).unwrap(),
+            "digraph{
+n2 [label=\"v_0\", shape=box]
+n1 -> n2 [label=\"0\"]
+n3 [label=\"c_0 = 1\", shape=box]
+n1 -> n3 [label=\"1\"]
+n1 [label=\"+\", shape=ellipse]
+n0 -> n1 [label=\"0\"]
+n4 -> n1 [label=\"\"]
+n4 [label=\"ln\", shape=ellipse]
+n0 -> n4 [label=\"1\"]
+n0 [label=\"*\", shape=ellipse]
+}
+"
+        );
+        let annotated = program.to_dot(&[1.0], Some(&[-1.0])).unwrap();
+        assert!(annotated.contains("n2 [label=\"v_0\\n= -1\", shape=box]"));
+        assert!(annotated.contains("n4 [label=\"ln\\n= 0\", shape=ellipse]"));
+        assert!(annotated.contains("n0 [label=\"*\\n= 0\", shape=ellipse]"));
+        assert_eq!(Program::new().to_dot(&[], None), None);
+    }
+
+    #[test]
+    fn test_escaped_labels() {
+        let program = Program::create(&[Instruction::Var(0), Instruction::Custom(&QUOTED)]);
+        let dot = program.to_dot(&[], Some(&[2.0])).unwrap();
+        assert!(dot.contains("n0 [label=\"say \\\"hi\\\\\\n= 2\", shape=ellipse]"));
+    }
+}
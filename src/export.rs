@@ -0,0 +1,475 @@
+use crate::{
+    expr::dag,
+    instructions::{Instruction, Program},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Rust,
+    C,
+    NumPy,
+}
+
+impl Language {
+    fn literal(self, v: f32) -> String {
+        match self {
+            Language::Rust if v.is_nan() => "f32::NAN".to_string(),
+            Language::Rust if v.is_infinite() => format!("({}f32::INFINITY)", sign(v)),
+            // Negative zero too, so negating or calling a method on it keeps its sign
+            Language::Rust if v.is_sign_negative() => format!("({v:?}f32)"),
+            Language::Rust => format!("{v:?}f32"),
+            Language::C if v.is_nan() => "NAN".to_string(),
+            Language::C if v.is_infinite() => format!("({}INFINITY)", sign(v)),
+            Language::C if v.is_sign_negative() => format!("({v:?}f)"),
+            Language::C => format!("{v:?}f"),
+            Language::NumPy if v.is_nan() => "np.float32(np.nan)".to_string(),
+            Language::NumPy if v.is_infinite() => format!("np.float32({}np.inf)", sign(v)),
+            // Every f32 is exactly representable as a Python float, so converting the exact
+            // value avoids rounding the decimal twice
+            Language::NumPy => format!("np.float32({:?})", v as f64),
+        }
+    }
+
+    fn var(self, idx: u8) -> String {
+        format!("v[{idx}]")
+    }
+
+    /// Expression applying `inst` with the protected semantics of `Program::run` to `args`,
+    /// which are variables or literals
+    fn operator(self, inst: Instruction, args: &[String]) -> String {
+        let a = args.first().map(String::as_str).unwrap_or_default();
+        let b = args.get(1).map(String::as_str).unwrap_or_default();
+        let binary = |op: &str| format!("{a} {op} {b}");
+        match (self, inst) {
+            (_, Instruction::Add) => binary("+"),
+            (_, Instruction::Sub) => binary("-"),
+            (_, Instruction::Mul) => binary("*"),
+            (_, Instruction::Neg) => format!("-{a}"),
+
+            (Language::Rust, Instruction::Div) => {
+                format!("if {b} == 0.0 {{ 1.0 }} else {{ {a} / {b} }}")
+            }
+            (Language::Rust, Instruction::Pow) => format!(
+                "{{ let p = {a}.powf({b}); if p.is_nan() {{ {a}.abs().powf({b}) }} else {{ p }} }}.clamp(-f32::MAX, f32::MAX)"
+            ),
+            (Language::Rust, Instruction::Exp) => format!("{a}.exp().clamp(-f32::MAX, f32::MAX)"),
+            (Language::Rust, Instruction::Log) => {
+                format!("if {a} == 0.0 {{ 0.0 }} else {{ {a}.abs().ln() }}")
+            }
+            (Language::Rust, Instruction::Sin) => format!("{a}.sin()"),
+            (Language::Rust, Instruction::Cos) => format!("{a}.cos()"),
+            (Language::Rust, Instruction::Tan) => format!("{a}.tan()"),
+            (Language::Rust, Instruction::Sqrt) => format!("{a}.abs().sqrt()"),
+            (Language::Rust, Instruction::Abs) => format!("{a}.abs()"),
+            (Language::Rust, Instruction::Lt) => format!("if {a} < {b} {{ 1.0 }} else {{ 0.0 }}"),
+            (Language::Rust, Instruction::Gt) => format!("if {a} > {b} {{ 1.0 }} else {{ 0.0 }}"),
+            (Language::Rust, Instruction::Select) => {
+                format!("if {a} != 0.0 {{ {b} }} else {{ {} }}", args[2])
+            }
+
+            (Language::C, Instruction::Div) => format!("({b} == 0.0f ? 1.0f : {a} / {b})"),
+            (Language::C, Instruction::Pow) => format!("protected_pow({a}, {b})"),
+            (Language::C, Instruction::Exp) => format!("saturate(expf({a}))"),
+            (Language::C, Instruction::Log) => format!("({a} == 0.0f ? 0.0f : logf(fabsf({a})))"),
+            (Language::C, Instruction::Sin) => format!("sinf({a})"),
+            (Language::C, Instruction::Cos) => format!("cosf({a})"),
+            (Language::C, Instruction::Tan) => format!("tanf({a})"),
+            (Language::C, Instruction::Sqrt) => format!("sqrtf(fabsf({a}))"),
+            (Language::C, Instruction::Abs) => format!("fabsf({a})"),
+            (Language::C, Instruction::Lt) => format!("({a} < {b} ? 1.0f : 0.0f)"),
+            (Language::C, Instruction::Gt) => format!("({a} > {b} ? 1.0f : 0.0f)"),
+            (Language::C, Instruction::Select) => format!("({a} != 0.0f ? {b} : {})", args[2]),
+
+            (Language::NumPy, Instruction::Div) => format!(
+                "np.where({b} == 0, np.float32(1), {a} / np.where({b} == 0, np.float32(1), {b}))"
+            ),
+            (Language::NumPy, Instruction::Pow) => format!(
+                "np.clip(np.where(np.isnan({a} ** {b}), np.abs({a}) ** {b}, {a} ** {b}), -MAX, MAX)"
+            ),
+            (Language::NumPy, Instruction::Exp) => format!("np.clip(np.exp({a}), -MAX, MAX)"),
+            (Language::NumPy, Instruction::Log) => {
+                format!("np.where({a} == 0, np.float32(0), np.log(np.abs({a})))")
+            }
+            (Language::NumPy, Instruction::Sin) => format!("np.sin({a})"),
+            (Language::NumPy, Instruction::Cos) => format!("np.cos({a})"),
+            (Language::NumPy, Instruction::Tan) => format!("np.tan({a})"),
+            (Language::NumPy, Instruction::Sqrt) => format!("np.sqrt(np.abs({a}))"),
+            (Language::NumPy, Instruction::Abs) => format!("np.abs({a})"),
+            (Language::NumPy, Instruction::Lt) => {
+                format!("np.where({a} < {b}, np.float32(1), np.float32(0))")
+            }
+            (Language::NumPy, Instruction::Gt) => {
+                format!("np.where({a} > {b}, np.float32(1), np.float32(0))")
+            }
+            (Language::NumPy, Instruction::Select) => {
+                format!("np.where({a} != 0, {b}, {})", args[2])
+            }
+
+            (_, Instruction::Custom(op)) => format!("{}({})", op.name(), args.join(", ")),
+            (_, inst) => unreachable!("{inst:?} is not an operator"),
+        }
+    }
+
+    fn function(self, name: &str, statements: &[(usize, String)], result: &str) -> String {
+        let mut out = String::new();
+        match self {
+            Language::Rust => {
+                out.push_str(&format!("pub fn {name}(v: &[f32]) -> f32 {{\n"));
+                for (t, expr) in statements {
+                    out.push_str(&format!("    let t{t} = {expr};\n"));
+                }
+                out.push_str(&format!("    {result}\n}}\n"));
+            }
+            Language::C => {
+                out.push_str("#include <float.h>\n#include <math.h>\n\n");
+                // Fused multiply-adds round differently than `Program::run`
+                out.push_str("#pragma STDC FP_CONTRACT OFF\n\n");
+                out.push_str(C_HELPERS);
+                out.push_str(&format!("float {name}(const float *v) {{\n"));
+                for (t, expr) in statements {
+                    out.push_str(&format!("    float t{t} = {expr};\n"));
+                }
+                out.push_str(&format!("    return {result};\n}}\n"));
+            }
+            Language::NumPy => {
+                out.push_str("import numpy as np\n\nMAX = np.finfo(np.float32).max\n\n\n");
+                out.push_str(&format!("def {name}(v):\n"));
+                out.push_str("    v = [np.asarray(column, dtype=np.float32) for column in v]\n");
+                out.push_str("    with np.errstate(all=\"ignore\"):\n");
+                for (t, expr) in statements {
+                    out.push_str(&format!("        t{t} = {expr}\n"));
+                }
+                out.push_str(&format!("        return {result}\n"));
+            }
+        }
+        out
+    }
+}
+
+fn sign(v: f32) -> &'static str {
+    if v < 0.0 {
+        "-"
+    } else {
+        ""
+    }
+}
+
+const C_HELPERS: &str = "static float saturate(float v) {
+    return v > FLT_MAX ? FLT_MAX : v < -FLT_MAX ? -FLT_MAX : v;
+}
+
+static float protected_pow(float a, float b) {
+    float p = powf(a, b);
+    return saturate(isnan(p) ? powf(fabsf(a), b) : p);
+}
+
+";
+
+impl Program {
+    fn export(&self, language: Language, name: &str, consts: &[f32]) -> Option<String> {
+        let (nodes, result) = dag(self)?;
+        // Only nodes the result depends on are emitted
+        let mut used = vec![false; nodes.len()];
+        used[result] = true;
+        for (i, node) in nodes.iter().enumerate().rev() {
+            if used[i] {
+                for operand in &node.operands {
+                    used[*operand] = true;
+                }
+            }
+        }
+        let mut values: Vec<String> = Vec::with_capacity(nodes.len());
+        let mut statements = Vec::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let value = match node.inst {
+                Instruction::Const(idx) => language.literal(*consts.get(idx as usize)?),
+                Instruction::Var(idx) => language.var(idx),
+                Instruction::Lit(v) => language.literal(v),
+                inst if used[i] => {
+                    let args = node
+                        .operands
+                        .iter()
+                        .map(|operand| values[*operand].clone())
+                        .collect::<Vec<_>>();
+                    let expr = language.operator(inst, &args);
+                    if i == result {
+                        expr
+                    } else {
+                        statements.push((statements.len(), expr));
+                        format!("t{}", statements.len() - 1)
+                    }
+                }
+                _ => String::new(),
+            };
+            values.push(value);
+        }
+        Some(language.function(name, &statements, &values[result]))
+    }
+
+    /// Exports the program as a standalone Rust function `pub fn name(v: &[f32]) -> f32` with the
+    /// `consts` inlined, which computes the same value as `Program::evaluate_to_result`.
+    /// Custom operators are called as functions with the operator's name, which have to be in scope.
+    pub fn to_rust(&self, name: &str, consts: &[f32]) -> Option<String> {
+        self.export(Language::Rust, name, consts)
+    }
+
+    /// Exports the program as a standalone C function `float name(const float *v)`, see
+    /// `Program::to_rust`
+    pub fn to_c(&self, name: &str, consts: &[f32]) -> Option<String> {
+        self.export(Language::C, name, consts)
+    }
+
+    /// Exports the program as a Python function `name(v)` evaluating `float32` NumPy arrays,
+    /// where `v[i]` is the column of `Var(i)`, see `Program::evaluate_batch` and `Program::to_rust`.
+    /// NumPy has its own implementations of `exp`, `log` and the trigonometric functions, so
+    /// results can differ from `Program::evaluate_batch` in the last bits.
+    pub fn to_numpy(&self, name: &str, consts: &[f32]) -> Option<String> {
+        self.export(Language::NumPy, name, consts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use crate::{Instruction, Program};
+
+    fn program() -> Program {
+        Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Div,
+            Instruction::Dup,
+            Instruction::Log,
+            Instruction::Var(1),
+            Instruction::Pow,
+            Instruction::Swap,
+            Instruction::Exp,
+            Instruction::Lit(-0.5),
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Sqrt,
+            Instruction::Drop,
+            Instruction::Sub,
+            Instruction::Var(0),
+            Instruction::Lit(0.0),
+            Instruction::Lt,
+            Instruction::Swap,
+            Instruction::Var(1),
+            Instruction::Select,
+        ])
+    }
+
+    const INPUTS: [[f32; 2]; 6] = [
+        [0.0, 0.0],
+        [1.5, -2.0],
+        [-3.0, 0.5],
+        [100.0, 30.0],
+        [-0.1, 1e-3],
+        [7.0, f32::INFINITY],
+    ];
+
+    #[test]
+    fn test_export_rust() {
+        assert_eq!(
+            program().to_rust("model", &[0.0]).unwrap(),
+            "pub fn model(v: &[f32]) -> f32 {
+    let t0 = if 0.0f32 == 0.0 { 1.0 } else { v[0] / 0.0f32 };
+    let t1 = if t0 == 0.0 { 0.0 } else { t0.abs().ln() };
+    let t2 = { let p = t1.powf(v[1]); if p.is_nan() { t1.abs().powf(v[1]) } else { p } }.clamp(-f32::MAX, f32::MAX);
+    let t3 = t0.exp().clamp(-f32::MAX, f32::MAX);
+    let t4 = t3 * (-0.5f32);
+    let t5 = t2 - t4;
+    let t6 = if v[0] < 0.0f32 { 1.0 } else { 0.0 };
+    if t6 != 0.0 { t5 } else { v[1] }
+}
+"
+        );
+        assert_eq!(Program::new().to_rust("model", &[]), None);
+        assert_eq!(program().to_rust("model", &[]), None);
+    }
+
+    #[test]
+    fn test_export_numpy() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Lit(0.1),
+            Instruction::Div,
+        ]);
+        assert_eq!(
+            program.to_numpy("model", &[]).unwrap(),
+            "import numpy as np
+
+MAX = np.finfo(np.float32).max
+
+
+def model(v):
+    v = [np.asarray(column, dtype=np.float32) for column in v]
+    with np.errstate(all=\"ignore\"):
+        return np.where(np.float32(0.10000000149011612) == 0, np.float32(1), v[0] / np.where(np.float32(0.10000000149011612) == 0, np.float32(1), np.float32(0.10000000149011612)))
+"
+        );
+    }
+
+    /// Programs and consts the exports are executed with
+    fn cases() -> Vec<(Program, [f32; 1])> {
+        let mut cases = [[0.0], [2.5], [-1e-3], [-0.0]]
+            .map(|consts| (program(), consts))
+            .to_vec();
+        // Negative zero must neither become `--0.0` nor lose its sign to a method call
+        cases.push((
+            Program::create(&[Instruction::Lit(-0.0), Instruction::Neg]),
+            [0.0],
+        ));
+        cases.push((
+            Program::create(&[Instruction::Lit(-0.0), Instruction::Sqrt]),
+            [0.0],
+        ));
+        cases
+    }
+
+    /// Distance of two floats in units in the last place, with `-0.0` one below `0.0`
+    fn ulps(a: f32, b: f32) -> u64 {
+        let ordered = |v: f32| {
+            let magnitude = (v.to_bits() & 0x7fff_ffff) as i64;
+            if v.is_sign_negative() {
+                -magnitude - 1
+            } else {
+                magnitude
+            }
+        };
+        ordered(a).abs_diff(ordered(b))
+    }
+
+    /// Builds an exported program in a temporary directory with `build` and compares the bits
+    /// `run` prints for every input with `Program::evaluate_to_result`, allowing a difference of
+    /// `max_ulps`. Returns `false` without comparing if the first program does not build because
+    /// the toolchain is missing.
+    fn matches_run(
+        name: &str,
+        max_ulps: u64,
+        export: impl Fn(&Program, &[f32]) -> String,
+        build: impl Fn(&Path) -> bool,
+        run: impl Fn(&Path, f32, f32) -> Command,
+    ) -> bool {
+        let dir = std::env::temp_dir().join(format!(
+            "evofunc_export_{name}_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, (program, consts)) in cases().into_iter().enumerate() {
+            let source = export(&program, &consts);
+            std::fs::write(dir.join(name), &source).unwrap();
+            if !build(&dir) {
+                std::fs::remove_dir_all(dir).unwrap();
+                assert_eq!(i, 0, "{name} does not build:\n{source}");
+                return false;
+            }
+            for [x, y] in INPUTS {
+                let output = run(&dir, x, y).output().unwrap();
+                let bits: u32 = String::from_utf8(output.stdout)
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap();
+                let expected = program.evaluate_to_result(&consts, &[x, y]).unwrap();
+                assert!(
+                    ulps(f32::from_bits(bits), expected) <= max_ulps,
+                    "{name} {program} {consts:?} {x} {y}: {} != {expected}",
+                    f32::from_bits(bits)
+                );
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+        true
+    }
+
+    fn builds(command: &mut Command) -> bool {
+        command.status().map(|s| s.success()).unwrap_or(false)
+    }
+
+    #[test]
+    fn test_export_c_matches_run() {
+        let export = |program: &Program, consts: &[f32]| {
+            let mut source = program.to_c("model", consts).unwrap();
+            source.push_str("#include <stdio.h>\n#include <stdlib.h>\n\n");
+            source.push_str("int main(int argc, char **argv) {\n");
+            source.push_str("    float v[2] = {strtof(argv[1], NULL), strtof(argv[2], NULL)};\n");
+            source.push_str("    float r = model(v);\n");
+            source.push_str("    unsigned int bits = *(unsigned int *)&r;\n");
+            source.push_str("    printf(\"%u\", bits);\n    return argc - 3;\n}\n");
+            source
+        };
+        let build = |dir: &Path| {
+            builds(Command::new("cc").current_dir(dir).args([
+                "-O2",
+                "-ffp-contract=off",
+                "-o",
+                "model",
+                "model.c",
+                "-lm",
+            ]))
+        };
+        let run = |dir: &Path, x: f32, y: f32| {
+            let mut command = Command::new(dir.join("model"));
+            command.args([format!("{x:?}"), format!("{y:?}")]);
+            command
+        };
+        if !matches_run("model.c", 0, export, build, run) {
+            eprintln!("skipping, no working C compiler");
+        }
+    }
+
+    #[test]
+    fn test_export_rust_matches_run() {
+        let export = |program: &Program, consts: &[f32]| {
+            let mut source = program.to_rust("model", consts).unwrap();
+            source.push_str("\nfn main() {\n");
+            source.push_str("    let v: Vec<f32> = std::env::args().skip(1).map(|a| a.parse().unwrap()).collect();\n");
+            source.push_str("    print!(\"{}\", model(&v).to_bits());\n}\n");
+            source
+        };
+        let build = |dir: &Path| {
+            builds(Command::new("rustc").current_dir(dir).args([
+                "-O",
+                "--edition",
+                "2021",
+                "-o",
+                "model",
+                "model.rs",
+            ]))
+        };
+        let run = |dir: &Path, x: f32, y: f32| {
+            let mut command = Command::new(dir.join("model"));
+            command.args([format!("{x:?}"), format!("{y:?}")]);
+            command
+        };
+        if !matches_run("model.rs", 0, export, build, run) {
+            eprintln!("skipping, no working rustc");
+        }
+    }
+
+    #[test]
+    fn test_export_numpy_matches_run() {
+        let export = |program: &Program, consts: &[f32]| {
+            let mut source = program.to_numpy("model", consts).unwrap();
+            source.push_str("\n\nimport sys\n\n");
+            source.push_str("columns = [np.array([int(a)], dtype=np.uint32).view(np.float32) for a in sys.argv[1:]]\n");
+            source.push_str("result = np.asarray(model(columns), dtype=np.float32).reshape(-1)[0]\n");
+            source.push_str("print(result.view(np.uint32))\n");
+            source
+        };
+        let build = |_: &Path| builds(Command::new("python3").args(["-c", "import numpy"]));
+        let run = |dir: &Path, x: f32, y: f32| {
+            let mut command = Command::new("python3");
+            command
+                .arg(dir.join("model.py"))
+                .args([x.to_bits().to_string(), y.to_bits().to_string()]);
+            command
+        };
+        // NumPy's SIMD kernels for transcendental functions are not libm's
+        if !matches_run("model.py", 16, export, build, run) {
+            eprintln!("skipping, python3 with numpy is not available");
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::instructions::{Instruction, Program};
+
+/// Expression tree of the value a program leaves on top of its stack
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Const(u8),
+    Var(u8),
+    Lit(f32),
+    /// Operator applied to its operands, which are stored in push order. The number of operands
+    /// has to match the arity of the operator, see `Expr::op` and `Expr::is_valid`.
+    Op(Instruction, Vec<Expr>),
+}
+
+/// Operation of a program with the positions of its operands in the node list, see `dag`
+pub(crate) struct DagNode {
+    pub(crate) inst: Instruction,
+    pub(crate) operands: Vec<usize>,
+}
+
+/// Runs the program symbolically like `Expr::from_program`, but into a list of nodes where values
+/// duplicated by `Dup` and `Over` share one node. Operands come before the nodes using them.
+/// Returns the nodes and the position of the result, `None` if the stack underflows or the
+/// program is empty.
+pub(crate) fn dag(program: &Program) -> Option<(Vec<DagNode>, usize)> {
+    let mut nodes: Vec<DagNode> = Vec::new();
+    let mut stack = Vec::new();
+    for inst in program.instructions.iter().copied() {
+        match inst {
+            Instruction::Dup => stack.push(*stack.last()?),
+            Instruction::Swap => {
+                let len = stack.len().checked_sub(2)?;
+                stack.swap(len, len + 1);
+            }
+            Instruction::Drop => {
+                stack.pop()?;
+            }
+            Instruction::Over => stack.push(*stack.get(stack.len().checked_sub(2)?)?),
+            Instruction::Rot => {
+                let len = stack.len().checked_sub(3)?;
+                stack[len..].rotate_left(1);
+            }
+            _ => {
+                let arity = inst.operator_arity().unwrap_or(0);
+                let operands = stack.split_off(stack.len().checked_sub(arity)?);
+                stack.push(nodes.len());
+                nodes.push(DagNode { inst, operands });
+            }
+        }
+    }
+    Some((nodes, stack.pop()?))
+}
+
+/// Pre-order iterator over an expression and all of its subtrees, see `Expr::subtrees`
+pub struct Subtrees<'a> {
+    stack: Vec<&'a Expr>,
+}
+
+impl<'a> Iterator for Subtrees<'a> {
+    type Item = &'a Expr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let expr = self.stack.pop()?;
+        self.stack.extend(expr.operands().iter().rev());
+        Some(expr)
+    }
+}
+
+impl Expr {
+    /// Builds the tree by symbolically running the program, stack manipulation duplicates or
+    /// rearranges subtrees. Returns `None` if the stack underflows or the program is empty.
+    pub fn from_program(program: &Program) -> Option<Self> {
+        let mut stack = Vec::new();
+        for inst in program.instructions.iter().copied() {
+            match inst {
+                Instruction::Const(idx) => stack.push(Expr::Const(idx)),
+                Instruction::Var(idx) => stack.push(Expr::Var(idx)),
+                Instruction::Lit(v) => stack.push(Expr::Lit(v)),
+                Instruction::Dup => {
+                    let a = stack.pop()?;
+                    stack.push(a.clone());
+                    stack.push(a);
+                }
+                Instruction::Swap => {
+                    let b = stack.pop()?;
+                    let a = stack.pop()?;
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Instruction::Drop => {
+                    stack.pop()?;
+                }
+                Instruction::Over => {
+                    let b = stack.pop()?;
+                    let a = stack.pop()?;
+                    stack.push(a.clone());
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Instruction::Rot => {
+                    let c = stack.pop()?;
+                    let b = stack.pop()?;
+                    let a = stack.pop()?;
+                    stack.push(b);
+                    stack.push(c);
+                    stack.push(a);
+                }
+                _ => {
+                    let arity = inst.operator_arity()?;
+                    if stack.len() < arity {
+                        return None;
+                    }
+                    let operands = stack.split_off(stack.len() - arity);
+                    stack.push(Expr::Op(inst, operands));
+                }
+            }
+        }
+        stack.pop()
+    }
+
+    /// Operator node, `None` if `inst` is not an operator or `operands` does not match its arity
+    pub fn op(inst: Instruction, operands: Vec<Expr>) -> Option<Self> {
+        (inst.operator_arity() == Some(operands.len())).then_some(Expr::Op(inst, operands))
+    }
+
+    /// Whether every `Op` node holds an operator with as many operands as its arity
+    pub fn is_valid(&self) -> bool {
+        self.subtrees().all(|expr| match expr {
+            Expr::Op(inst, operands) => inst.operator_arity() == Some(operands.len()),
+            _ => true,
+        })
+    }
+
+    /// Emits the tree as a postfix program without stack manipulation instructions, `None` if the
+    /// tree is not valid, see `Expr::is_valid`
+    pub fn to_program(&self) -> Option<Program> {
+        self.is_valid().then(|| self.to_program_unchecked())
+    }
+
+    /// `Expr::to_program` of a tree that is known to be valid, e.g. built by `Expr::from_program`
+    pub(crate) fn to_program_unchecked(&self) -> Program {
+        let mut program = Program::new();
+        self.emit(&mut program);
+        program
+    }
+
+    fn emit(&self, program: &mut Program) {
+        match self {
+            Expr::Const(idx) => program.push_inst(Instruction::Const(*idx)),
+            Expr::Var(idx) => program.push_inst(Instruction::Var(*idx)),
+            Expr::Lit(v) => program.push_inst(Instruction::Lit(*v)),
+            Expr::Op(inst, operands) => {
+                for operand in operands {
+                    operand.emit(program);
+                }
+                program.push_inst(*inst);
+            }
+        }
+    }
+
+    /// Operands of an operator, empty for leaves
+    pub fn operands(&self) -> &[Expr] {
+        match self {
+            Expr::Op(_, operands) => operands,
+            _ => &[],
+        }
+    }
+
+    /// Number of nodes on the longest path from the root to a leaf, a leaf has depth 1
+    pub fn depth(&self) -> usize {
+        1 + self.operands().iter().map(Expr::depth).max().unwrap_or(0)
+    }
+
+    /// Number of nodes in the tree
+    pub fn size(&self) -> usize {
+        1 + self.operands().iter().map(Expr::size).sum::<usize>()
+    }
+
+    /// Iterates over the tree and all of its subtrees in pre-order, the position of a subtree in
+    /// this order is its index for `Expr::subtree` and `Expr::replace_subtree`
+    pub fn subtrees(&self) -> Subtrees<'_> {
+        Subtrees { stack: vec![self] }
+    }
+
+    /// Subtree at the pre-order `index`, the tree itself has index 0
+    pub fn subtree(&self, index: usize) -> Option<&Expr> {
+        self.subtrees().nth(index)
+    }
+
+    /// Mutable subtree at the pre-order `index`
+    pub fn subtree_mut(&mut self, mut index: usize) -> Option<&mut Expr> {
+        let mut expr = self;
+        loop {
+            if index == 0 {
+                return Some(expr);
+            }
+            index -= 1;
+            let Expr::Op(_, operands) = expr else {
+                return None;
+            };
+            let mut operands = operands.iter_mut();
+            expr = loop {
+                let operand = operands.next()?;
+                let size = operand.size();
+                if index < size {
+                    break operand;
+                }
+                index -= size;
+            };
+        }
+    }
+
+    /// Replaces the subtree at the pre-order `index` and returns the replaced subtree, `None` if
+    /// the tree has no subtree with this index
+    pub fn replace_subtree(&mut self, index: usize, replacement: Expr) -> Option<Expr> {
+        self.subtree_mut(index)
+            .map(|subtree| std::mem::replace(subtree, replacement))
+    }
+
+    /// Renders in the fully parenthesized syntax of `Program::render_pretty`, returns `None` if
+    /// the tree reads a constant that is not in `consts` or is not valid
+    pub fn render_pretty(&self, consts: &[f32]) -> Option<String> {
+        Some(match self {
+            Expr::Const(ci) => format!("{}", consts.get(*ci as usize)?),
+            Expr::Var(vi) => format!("v_{vi}"),
+            Expr::Lit(v) => format!("{}", v),
+            Expr::Op(inst, operands) => {
+                if inst.operator_arity() != Some(operands.len()) {
+                    return None;
+                }
+                let mut c = operands
+                    .iter()
+                    .map(|c| c.render_pretty(consts))
+                    .collect::<Option<Vec<_>>>()?;
+                // `-2**x` reads as `-(2**x)`, so negative numbers need parentheses as a base
+                if *inst == Instruction::Pow
+                    && matches!(operands[0], Expr::Const(_) | Expr::Lit(_))
+                    && c[0].starts_with('-')
+                {
+                    c[0] = format!("({})", c[0]);
+                }
+                match inst {
+                    Instruction::Add => format!("({} + {})", c[0], c[1]),
+                    Instruction::Sub => format!("({} - {})", c[0], c[1]),
+                    Instruction::Mul => format!("({} * {})", c[0], c[1]),
+                    Instruction::Div => format!("({} / {})", c[0], c[1]),
+                    Instruction::Pow => format!("({}**{})", c[0], c[1]),
+                    Instruction::Lt => format!("({} < {})", c[0], c[1]),
+                    Instruction::Gt => format!("({} > {})", c[0], c[1]),
+                    Instruction::Exp => format!("(e**{})", c[0]),
+                    Instruction::Log => format!("(ln({}))", c[0]),
+                    Instruction::Sin => format!("(sin({}))", c[0]),
+                    Instruction::Cos => format!("(cos({}))", c[0]),
+                    Instruction::Tan => format!("(tan({}))", c[0]),
+                    Instruction::Sqrt => format!("(sqrt({}))", c[0]),
+                    Instruction::Abs => format!("(abs({}))", c[0]),
+                    Instruction::Neg => format!("(-{})", c[0]),
+                    Instruction::Select => format!("({} if {} else {})", c[1], c[0], c[2]),
+                    Instruction::Custom(op) => op.render(&c),
+                    _ => unreachable!("{inst:?} is not an operator"),
+                }
+            }
+        })
+    }
+}
+
+impl Program {
+    /// Expression tree of the value the program leaves on top of its stack, see
+    /// `Expr::from_program`
+    pub fn to_expr(&self) -> Option<Expr> {
+        Expr::from_program(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Expr, Instruction, Program};
+
+    #[test]
+    fn test_program_round_trip() {
+        // sin(v_0 * v_0) + c_0
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Dup,
+            Instruction::Mul,
+            Instruction::Sin,
+            Instruction::Const(0),
+            Instruction::Add,
+        ]);
+        let expr = program.to_expr().unwrap();
+        assert_eq!(
+            expr,
+            Expr::Op(
+                Instruction::Add,
+                vec![
+                    Expr::Op(
+                        Instruction::Sin,
+                        vec![Expr::Op(
+                            Instruction::Mul,
+                            vec![Expr::Var(0), Expr::Var(0)]
+                        )]
+                    ),
+                    Expr::Const(0)
+                ]
+            )
+        );
+        assert_eq!(
+            expr.to_program().unwrap().render(),
+            "Var(0) Var(0) Mul Sin Const(0) Add"
+        );
+        assert_eq!(Program::new().to_expr(), None);
+    }
+
+    #[test]
+    fn test_subtrees() {
+        let mut expr = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Mul,
+            Instruction::Sin,
+            Instruction::Const(0),
+            Instruction::Add,
+        ])
+        .to_expr()
+        .unwrap();
+        assert_eq!(expr.depth(), 4);
+        assert_eq!(expr.size(), 6);
+        let leaves = expr
+            .subtrees()
+            .filter(|e| e.operands().is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, [&Expr::Var(0), &Expr::Var(1), &Expr::Const(0)]);
+
+        // Replace v_0 * v_1 with v_2
+        assert_eq!(expr.subtree(2), Some(&expr.operands()[0].operands()[0]));
+        let replaced = expr.replace_subtree(2, Expr::Var(2)).unwrap();
+        assert_eq!(replaced.render_pretty(&[]).unwrap(), "(v_0 * v_1)");
+        assert_eq!(expr.render_pretty(&[1.5]).unwrap(), "((sin(v_2)) + 1.5)");
+        assert_eq!(expr.render_pretty(&[]), None);
+        assert_eq!(expr.replace_subtree(3, Expr::Lit(2.0)), Some(Expr::Const(0)));
+        assert_eq!(expr.replace_subtree(4, Expr::Lit(2.0)), None);
+        assert_eq!(expr.depth(), 3);
+    }
+
+    #[test]
+    fn test_malformed_operators() {
+        assert_eq!(Expr::op(Instruction::Add, vec![Expr::Var(0)]), None);
+        assert_eq!(Expr::op(Instruction::Dup, vec![Expr::Var(0)]), None);
+        let neg = Expr::op(Instruction::Neg, vec![Expr::Var(0)]).unwrap();
+        assert!(neg.is_valid());
+        assert_eq!(neg.to_program().unwrap().render(), "Var(0) Neg");
+
+        for malformed in [
+            Expr::Op(Instruction::Add, vec![Expr::Var(0)]),
+            Expr::Op(Instruction::Dup, vec![Expr::Var(0)]),
+            Expr::Op(
+                Instruction::Sin,
+                vec![Expr::Op(Instruction::Lit(1.0), vec![])],
+            ),
+        ] {
+            let mut expr = neg.clone();
+            expr.replace_subtree(1, malformed);
+            assert!(!expr.is_valid());
+            assert_eq!(expr.to_program(), None);
+            assert_eq!(expr.render_pretty(&[]), None);
+        }
+    }
+}
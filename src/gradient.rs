@@ -0,0 +1,332 @@
+use crate::{
+    instructions::{EvalError, Instruction, Program, STACKSIZE},
+    scalar::Scalar,
+    semantics::Semantics,
+};
+
+/// Value of a program together with its partial derivatives
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient<T = f32> {
+    pub value: T,
+    /// Partial derivative with respect to every entry of `consts`
+    pub d_consts: Vec<T>,
+    /// Partial derivative with respect to every entry of `vars`
+    pub d_vars: Vec<T>,
+}
+
+/// Dual number carrying the derivatives with respect to all consts followed by all vars
+#[derive(Clone)]
+struct Dual<T> {
+    value: T,
+    grad: Vec<T>,
+}
+
+impl<T: Scalar> Dual<T> {
+    fn constant(value: T, len: usize) -> Self {
+        Self {
+            value,
+            grad: vec![T::ZERO; len],
+        }
+    }
+
+    /// `value` with the derivative `da * a' + db * b'`
+    fn chain2(value: T, a: &Dual<T>, da: T, b: &Dual<T>, db: T) -> Self {
+        Self {
+            value,
+            grad: a
+                .grad
+                .iter()
+                .zip(b.grad.iter())
+                .map(|(ga, gb)| term(da, *ga) + term(db, *gb))
+                .collect(),
+        }
+    }
+
+    /// `value` with the derivative `da * a'`
+    fn chain(value: T, a: &Dual<T>, da: T) -> Self {
+        Self {
+            value,
+            grad: a.grad.iter().map(|ga| term(da, *ga)).collect(),
+        }
+    }
+}
+
+/// `d * g`, but zero if the operand does not depend on the input at all, even where the operator
+/// is not differentiable and `d` is infinite
+fn term<T: Scalar>(d: T, g: T) -> T {
+    if g == T::ZERO {
+        T::ZERO
+    } else {
+        d * g
+    }
+}
+
+fn sign<T: Scalar>(v: T) -> T {
+    if v > T::ZERO {
+        T::ONE
+    } else if v < T::ZERO {
+        -T::ONE
+    } else {
+        T::ZERO
+    }
+}
+
+fn binary<T: Scalar>(inst: Instruction, semantics: Semantics, a: &Dual<T>, b: &Dual<T>) -> Dual<T> {
+    let value = inst.apply_binary(semantics, a.value, b.value);
+    let protected = semantics == Semantics::Protected;
+    let (x, y) = (a.value, b.value);
+    let (da, db) = match inst {
+        Instruction::Add => (T::ONE, T::ONE),
+        Instruction::Sub => (T::ONE, -T::ONE),
+        Instruction::Mul => (y, x),
+        // Protected division by zero is the constant 1
+        Instruction::Div if protected && y == T::ZERO => (T::ZERO, T::ZERO),
+        Instruction::Div => (T::ONE / y, -x / (y * y)),
+        // Saturated results do not change with the inputs
+        Instruction::Pow if protected && value.abs() == T::MAX => (T::ZERO, T::ZERO),
+        Instruction::Pow => {
+            // Protected pow falls back to |x| where x^y is not defined
+            let fallback = protected && x.powf(y).is_nan();
+            let base = if fallback { x.abs() } else { x };
+            // x^0 is 1 for every x, including 0 where 0 * 0^-1 would be NaN
+            let da = if y == T::ZERO {
+                T::ZERO
+            } else {
+                y * base.powf(y - T::ONE)
+            };
+            let da = if fallback { da * sign(x) } else { da };
+            // 0^y is constant for positive y, which also defines the derivative where ln(0) is
+            // infinite
+            let db = if value == T::ZERO || x == T::ZERO {
+                T::ZERO
+            } else {
+                value * x.abs().ln()
+            };
+            (da, db)
+        }
+        Instruction::Lt | Instruction::Gt => (T::ZERO, T::ZERO),
+        _ => unreachable!("{:?} is not a binary operator", inst),
+    };
+    Dual::chain2(value, a, da, b, db)
+}
+
+fn unary<T: Scalar>(inst: Instruction, semantics: Semantics, a: &Dual<T>) -> Dual<T> {
+    let value = inst.apply_unary(semantics, a.value);
+    let protected = semantics == Semantics::Protected;
+    let x = a.value;
+    let two = T::ONE + T::ONE;
+    let d = match inst {
+        Instruction::Exp if protected && value == T::MAX => T::ZERO,
+        Instruction::Exp => value,
+        Instruction::Log if protected && x == T::ZERO => T::ZERO,
+        // d/dx ln|x| is 1/x as well
+        Instruction::Log => T::ONE / x,
+        Instruction::Sin => x.cos(),
+        Instruction::Cos => -x.sin(),
+        Instruction::Tan => T::ONE + value * value,
+        // Like `Abs`, the kink of sqrt|x| at zero gets the derivative zero
+        Instruction::Sqrt if protected && x == T::ZERO => T::ZERO,
+        Instruction::Sqrt if protected => sign(x) / (two * value),
+        Instruction::Sqrt => T::ONE / (two * value),
+        Instruction::Abs => sign(x),
+        Instruction::Neg => -T::ONE,
+        _ => unreachable!("{:?} is not a unary operator", inst),
+    };
+    Dual::chain(value, a, d)
+}
+
+impl Program {
+    /// Evaluates the program with forward-mode automatic differentiation, returning the result
+    /// and its partial derivatives with respect to every constant and variable.
+    ///
+    /// Protected operators are differentiated piecewise, so where an operator is replaced by a
+    /// constant (e.g. division by zero) its derivative is zero. Custom operators use
+    /// `Operator::derivative`.
+    pub fn evaluate_gradient<T: Scalar>(
+        &self,
+        consts: &[T],
+        vars: &[T],
+    ) -> Result<Gradient<T>, EvalError> {
+        self.evaluate_gradient_with(Semantics::default(), consts, vars)
+    }
+
+    pub fn evaluate_gradient_with<T: Scalar>(
+        &self,
+        semantics: Semantics,
+        consts: &[T],
+        vars: &[T],
+    ) -> Result<Gradient<T>, EvalError> {
+        let len = consts.len() + vars.len();
+        let mut stack: Vec<Dual<T>> = Vec::new();
+        for (index, inst) in self.instructions.iter().copied().enumerate() {
+            let (pops, _) = inst.stack_effect();
+            if stack.len() < pops {
+                return Err(EvalError::StackUnderflow { index });
+            }
+            let seed = |value: T, position: usize| {
+                let mut dual = Dual::constant(value, len);
+                dual.grad[position] = T::ONE;
+                dual
+            };
+            match inst {
+                Instruction::Const(idx) => {
+                    let v = consts.get(idx as usize).ok_or(EvalError::ConstOutOfRange {
+                        index,
+                        const_index: idx,
+                    })?;
+                    stack.push(seed(*v, idx as usize));
+                }
+                Instruction::Var(idx) => {
+                    let v = vars.get(idx as usize).ok_or(EvalError::VarOutOfRange {
+                        index,
+                        var_index: idx,
+                    })?;
+                    stack.push(seed(*v, consts.len() + idx as usize));
+                }
+                Instruction::Lit(v) => stack.push(Dual::constant(T::from_f32(v), len)),
+                Instruction::Dup => stack.push(stack[stack.len() - 1].clone()),
+                Instruction::Over => stack.push(stack[stack.len() - 2].clone()),
+                Instruction::Swap => {
+                    let l = stack.len();
+                    stack.swap(l - 1, l - 2);
+                }
+                Instruction::Drop => {
+                    stack.pop();
+                }
+                Instruction::Rot => {
+                    let l = stack.len();
+                    stack[l - 3..].rotate_left(1);
+                }
+                _ => {
+                    let operands = stack.split_off(stack.len() - pops);
+                    let result = match inst {
+                        Instruction::Select => {
+                            let taken = if operands[0].value != T::ZERO { 1 } else { 2 };
+                            operands[taken].clone()
+                        }
+                        Instruction::Custom(op) => {
+                            let args = operands
+                                .iter()
+                                .map(|o| o.value.to_f64())
+                                .collect::<Vec<_>>();
+                            let mut result = Dual::constant(
+                                T::from_f64(op.eval_f64(&args)),
+                                len,
+                            );
+                            for (i, operand) in operands.iter().enumerate() {
+                                let d = T::from_f64(op.derivative(&args, i));
+                                for (g, og) in result.grad.iter_mut().zip(operand.grad.iter()) {
+                                    *g = *g + term(d, *og);
+                                }
+                            }
+                            result
+                        }
+                        _ if pops == 2 => binary(inst, semantics, &operands[0], &operands[1]),
+                        _ => unary(inst, semantics, &operands[0]),
+                    };
+                    semantics
+                        .check(result.value)
+                        .ok_or(EvalError::NonFinite { index })?;
+                    stack.push(result);
+                }
+            }
+            if stack.len() > STACKSIZE {
+                return Err(EvalError::StackOverflow { index });
+            }
+        }
+        let mut result = stack.pop().ok_or(EvalError::EmptyResult)?;
+        let d_vars = result.grad.split_off(consts.len());
+        Ok(Gradient {
+            value: result.value,
+            d_consts: result.grad,
+            d_vars,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Program};
+
+    #[test]
+    fn test_gradient_matches_finite_differences() {
+        // c_0 * sin(v_0 * v_1) / (c_1 + v_0^2) + ln(c_1)
+        let program = Program::create(&[
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Mul,
+            Instruction::Sin,
+            Instruction::Mul,
+            Instruction::Const(1),
+            Instruction::Var(0),
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Add,
+            Instruction::Div,
+            Instruction::Const(1),
+            Instruction::Log,
+            Instruction::Add,
+        ]);
+        let consts = [1.5f64, 2.0];
+        let vars = [0.7f64, -1.3];
+        let gradient = program.evaluate_gradient(&consts, &vars).unwrap();
+        assert_eq!(
+            gradient.value,
+            program.evaluate_to_result(&consts, &vars).unwrap()
+        );
+        // Central differences, shifting one of the four inputs at a time
+        let h = 1e-6;
+        let inputs = [consts[0], consts[1], vars[0], vars[1]];
+        let evaluate = |inputs: [f64; 4]| {
+            program
+                .evaluate_to_result(&inputs[..2], &inputs[2..])
+                .unwrap()
+        };
+        let analytic = [gradient.d_consts.clone(), gradient.d_vars.clone()].concat();
+        for i in 0..4 {
+            let (mut upper, mut lower) = (inputs, inputs);
+            upper[i] += h;
+            lower[i] -= h;
+            let numeric = (evaluate(upper) - evaluate(lower)) / (2.0 * h);
+            assert!((analytic[i] - numeric).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_gradient_of_protected_operators() {
+        let div = Program::create(&[Instruction::Var(0), Instruction::Var(1), Instruction::Div]);
+        let gradient = div.evaluate_gradient(&[], &[3.0f32, 0.0]).unwrap();
+        assert_eq!(gradient.value, 1.0);
+        assert_eq!(gradient.d_vars, vec![0.0, 0.0]);
+
+        let square = Program::create(&[Instruction::Var(0), Instruction::Dup, Instruction::Mul]);
+        let gradient = square.evaluate_gradient(&[], &[3.0f32]).unwrap();
+        assert_eq!(gradient.d_vars, vec![6.0]);
+        assert!(gradient.d_consts.is_empty());
+
+        let sqrt = Program::create(&[Instruction::Var(0), Instruction::Sqrt]);
+        let gradient = sqrt.evaluate_gradient(&[], &[0.0f32]).unwrap();
+        assert_eq!(gradient.d_vars, vec![0.0]);
+    }
+
+    #[test]
+    fn test_gradient_at_singularity() {
+        // v_0^v_1 at (0, 0.5), where the derivative with respect to v_0 is infinite
+        let pow = Program::create(&[Instruction::Var(0), Instruction::Var(1), Instruction::Pow]);
+        let gradient = pow.evaluate_gradient(&[], &[0.0f32, 0.5]).unwrap();
+        assert_eq!(gradient.value, 0.0);
+        assert_eq!(gradient.d_vars, vec![f32::INFINITY, 0.0]);
+        // Unrelated inputs stay zero instead of becoming `inf * 0`
+        let gradient = pow.evaluate_gradient(&[1.0f32], &[0.0, 0.5]).unwrap();
+        assert_eq!(gradient.d_consts, vec![0.0]);
+        // 0^0 is 1 and stays finite in both directions
+        let gradient = pow.evaluate_gradient(&[], &[0.0f32, 0.0]).unwrap();
+        assert_eq!(gradient.value, 1.0);
+        assert_eq!(gradient.d_vars, vec![0.0, 0.0]);
+        let constant =
+            Program::create(&[Instruction::Var(0), Instruction::Lit(0.0), Instruction::Pow]);
+        let gradient = constant.evaluate_gradient(&[], &[0.0f32]).unwrap();
+        assert_eq!(gradient.d_vars, vec![0.0]);
+    }
+}
@@ -1,5 +1,9 @@
+use std::{fmt::Display, hash::{Hash, Hasher}};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+use crate::{expr::Expr, operator::Operator, scalar::Scalar, semantics::{saturate, Semantics}};
+
+
+#[derive(Debug, Copy, Clone)]
 pub enum Instruction{
     Add,
     Sub,
@@ -7,17 +11,130 @@ pub enum Instruction{
     Div,
     Exp,
     Log,
+    Sin,
+    Cos,
+    Tan,
+    Pow,
+    Sqrt,
+    Abs,
+    Neg,
+    Lt,
+    Gt,
+    /// `cond then else Select` pushes `then` if `cond` is non-zero and `else` otherwise
+    Select,
+    Dup,
+    Swap,
+    Drop,
+    Over,
+    Rot,
     Const(u8),
-    Var(u8)
+    Var(u8),
+    /// Literal constant stored inside the program itself, so it does not need a `consts` table entry
+    Lit(f32),
+    /// User-defined operator, see [`Operator`]
+    Custom(&'static dyn Operator)
+}
+
+// Literals are compared bitwise so that instructions can be used as keys and in instruction sets
+impl PartialEq for Instruction{
+    fn eq(&self, other: &Self) -> bool{
+        match (self, other){
+            (Instruction::Const(a), Instruction::Const(b)) => a == b,
+            (Instruction::Var(a), Instruction::Var(b)) => a == b,
+            (Instruction::Lit(a), Instruction::Lit(b)) => a.to_bits() == b.to_bits(),
+            (Instruction::Custom(a), Instruction::Custom(b)) => a.name() == b.name() && a.arity() == b.arity(),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other)
+        }
+    }
+}
+
+impl Eq for Instruction{}
+
+impl Hash for Instruction{
+    fn hash<H: Hasher>(&self, state: &mut H){
+        std::mem::discriminant(self).hash(state);
+        match self{
+            Instruction::Const(idx) | Instruction::Var(idx) => idx.hash(state),
+            Instruction::Lit(v) => v.to_bits().hash(state),
+            Instruction::Custom(op) => {
+                op.name().hash(state);
+                op.arity().hash(state);
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Instruction{
+    /// Applies a binary operator to `a` (pushed first) and `b` (pushed last)
+    pub(crate) fn apply_binary<T: Scalar>(&self, semantics: Semantics, a: T, b: T) -> T{
+        let protected = semantics == Semantics::Protected;
+        match self{
+            Instruction::Add => a + b,
+            Instruction::Sub => a - b,
+            Instruction::Mul => a * b,
+            Instruction::Div => if protected && b == T::ZERO { T::ONE } else { a / b },
+            Instruction::Pow => match a.powf(b){
+                v if protected && v.is_nan() => saturate(a.abs().powf(b)),
+                v if protected => saturate(v),
+                v => v
+            },
+            Instruction::Lt => if a < b { T::ONE } else { T::ZERO },
+            Instruction::Gt => if a > b { T::ONE } else { T::ZERO },
+            _ => unreachable!("{:?} is not a binary operator", self)
+        }
+    }
+
+    pub(crate) fn apply_unary<T: Scalar>(&self, semantics: Semantics, v: T) -> T{
+        let protected = semantics == Semantics::Protected;
+        match self{
+            Instruction::Exp => if protected { saturate(v.exp()) } else { v.exp() },
+            Instruction::Log => match protected{
+                true if v == T::ZERO => T::ZERO,
+                true => v.abs().ln(),
+                false => v.ln()
+            },
+            Instruction::Sin => v.sin(),
+            Instruction::Cos => v.cos(),
+            Instruction::Tan => v.tan(),
+            Instruction::Sqrt => if protected { v.abs().sqrt() } else { v.sqrt() },
+            Instruction::Abs => v.abs(),
+            Instruction::Neg => -v,
+            _ => unreachable!("{:?} is not a unary operator", self)
+        }
+    }
+
+    /// Number of operands of an operator, `None` for leaves and stack manipulation
+    pub(crate) fn operator_arity(&self) -> Option<usize>{
+        match self{
+            Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div | Instruction::Pow | Instruction::Lt | Instruction::Gt => Some(2),
+            Instruction::Exp | Instruction::Log | Instruction::Sin | Instruction::Cos | Instruction::Tan | Instruction::Sqrt | Instruction::Abs | Instruction::Neg => Some(1),
+            Instruction::Select => Some(3),
+            Instruction::Custom(op) => Some(op.arity()),
+            Instruction::Dup | Instruction::Swap | Instruction::Drop | Instruction::Over | Instruction::Rot | Instruction::Const(_) | Instruction::Var(_) | Instruction::Lit(_) => None
+        }
+    }
+
+    /// `cond then else Select`
+    pub(crate) fn apply_select<T: Scalar>(cond: T, t: T, f: T) -> T{
+        if cond != T::ZERO { t } else { f }
+    }
 }
 
 pub const STACKSIZE: usize = 128;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program{
     pub instructions: Vec<Instruction>
 }
 
+impl Default for Program{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 impl Program{
     pub fn new() -> Self{
         Self{instructions: Vec::new()}
@@ -35,6 +152,10 @@ impl Program{
         self.instructions.len()
     }
 
+    pub fn is_empty(&self) -> bool{
+        self.instructions.is_empty()
+    }
+
     pub fn truncate_to_len(&mut self, len: usize){
         self.instructions.truncate(len);
     }
@@ -47,156 +168,211 @@ impl Program{
         Self{instructions: insts.to_vec()}
     }
 
-    pub fn evaluate_to_result(&self, consts: &[f32], vars: &[f32]) -> Option<f32>{
-        self.run(consts, vars).and_then(|mut s| s.pop())
+    /// Replaces every `Const` with a `Lit` holding its value, making the program independent of `consts`
+    pub fn inline_consts(&self, consts: &[f32]) -> Option<Self>{
+        let instructions = self.instructions.iter().map(|inst| match inst{
+            Instruction::Const(idx) => consts.get(*idx as usize).map(|v| Instruction::Lit(*v)),
+            _ => Some(*inst)
+        }).collect::<Option<Vec<_>>>()?;
+        Some(Self{instructions})
+    }
+
+    pub fn evaluate_to_result<T: Scalar>(&self, consts: &[T], vars: &[T]) -> Result<T, EvalError>{
+        self.evaluate_to_result_with(Semantics::default(), consts, vars)
+    }
+
+    pub fn evaluate_to_result_with<T: Scalar>(&self, semantics: Semantics, consts: &[T], vars: &[T]) -> Result<T, EvalError>{
+        self.run_with(semantics, consts, vars)?.pop().ok_or(EvalError::EmptyResult)
+    }
+
+    pub fn evaluate_to_result_and_remaining_stack<T: Scalar>(&self, consts: &[T], vars: &[T]) -> Result<(T, usize), EvalError>{
+        let mut stack = self.run(consts, vars)?;
+        let result = stack.pop().ok_or(EvalError::EmptyResult)?;
+        Ok((result, stack.len()))
     }
 
-    pub fn evaluate_to_result_and_remaining_stack(&self, consts: &[f32], vars: &[f32]) -> Option<(f32, usize)>{
-        self.run(consts, vars).and_then(|mut s| Some((s.pop()?, s.len())))
+    pub fn run<T: Scalar>(&self, consts: &[T], vars: &[T]) -> Result<Stack<T>, EvalError>{
+        self.run_with(Semantics::default(), consts, vars)
     }
 
-    pub fn run(&self, consts: &[f32], vars: &[f32]) -> Option<Stack>{
+    /// Runs the program with the given operator semantics, with `Semantics::Checked` an operator
+    /// producing a non-finite value fails with `EvalError::NonFinite`
+    pub fn run_with<T: Scalar>(&self, semantics: Semantics, consts: &[T], vars: &[T]) -> Result<Stack<T>, EvalError>{
         let mut stack = Stack::new();
-        for inst in self.instructions.iter(){
+        for (index, inst) in self.instructions.iter().enumerate(){
+            let underflow = EvalError::StackUnderflow { index };
+            let overflow = EvalError::StackOverflow { index };
+            let non_finite = EvalError::NonFinite { index };
             match inst{
-                Instruction::Add => {
-                    let b = stack.pop()?;
-                    let a = stack.pop()?;
-                    stack.push(a + b);
+                Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div | Instruction::Pow | Instruction::Lt | Instruction::Gt => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    let v = semantics.check(inst.apply_binary(semantics, a, b)).ok_or(non_finite)?;
+                    stack.push(v).ok_or(overflow)?;
                 },
-                Instruction::Sub => {
-                    let b = stack.pop()?;
-                    let a = stack.pop()?;
-                    stack.push(a - b);
+                Instruction::Exp | Instruction::Log | Instruction::Sin | Instruction::Cos | Instruction::Tan | Instruction::Sqrt | Instruction::Abs | Instruction::Neg => {
+                    let v = stack.pop().ok_or(underflow)?;
+                    let v = semantics.check(inst.apply_unary(semantics, v)).ok_or(non_finite)?;
+                    stack.push(v).ok_or(overflow)?;
                 },
-                Instruction::Mul => {
-                    let b = stack.pop()?;
-                    let a = stack.pop()?;
-                    stack.push(a * b);
+                Instruction::Select => {
+                    let f = stack.pop().ok_or(underflow)?;
+                    let t = stack.pop().ok_or(underflow)?;
+                    let c = stack.pop().ok_or(underflow)?;
+                    stack.push(Instruction::apply_select(c, t, f)).ok_or(overflow)?;
                 },
-                Instruction::Div => {
-                    let b = stack.pop()?;
-                    let a = stack.pop()?;
-                    if b == 0.0{
-                        stack.push(1.0);
-                    }else{
-                        stack.push(a / b);
-                    }
+                Instruction::Dup => {
+                    let v = stack.pop().ok_or(underflow)?;
+                    stack.push(v).ok_or(overflow)?;
+                    stack.push(v).ok_or(overflow)?;
                 },
-                Instruction::Exp => {
-                    let v = stack.pop()?;
-                    stack.push(v.exp());
+                Instruction::Swap => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push(b).ok_or(overflow)?;
+                    stack.push(a).ok_or(overflow)?;
                 },
-                Instruction::Log => {
-                    let v = stack.pop()?.min(f32::MIN_POSITIVE);
-                    stack.push(v.ln());
+                Instruction::Drop => {
+                    stack.pop().ok_or(underflow)?;
+                },
+                Instruction::Over => {
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push(a).ok_or(overflow)?;
+                    stack.push(b).ok_or(overflow)?;
+                    stack.push(a).ok_or(overflow)?;
+                },
+                Instruction::Rot => {
+                    let c = stack.pop().ok_or(underflow)?;
+                    let b = stack.pop().ok_or(underflow)?;
+                    let a = stack.pop().ok_or(underflow)?;
+                    stack.push(b).ok_or(overflow)?;
+                    stack.push(c).ok_or(overflow)?;
+                    stack.push(a).ok_or(overflow)?;
                 },
                 Instruction::Const(idx) => {
-                    let v = consts[*idx as usize];
-                    stack.push(v);
+                    let v = *consts.get(*idx as usize).ok_or(EvalError::ConstOutOfRange { index, const_index: *idx })?;
+                    stack.push(v).ok_or(overflow)?;
                 },
                 Instruction::Var(idx) => {
-                    let v = vars[*idx as usize];
-                    stack.push(v);
+                    let v = *vars.get(*idx as usize).ok_or(EvalError::VarOutOfRange { index, var_index: *idx })?;
+                    stack.push(v).ok_or(overflow)?;
+                },
+                Instruction::Lit(v) => {
+                    stack.push(T::from_f32(*v)).ok_or(overflow)?;
+                },
+                Instruction::Custom(op) => {
+                    let v = T::eval_operator(*op, stack.pop_n(op.arity()).ok_or(underflow)?);
+                    stack.push(semantics.check(v).ok_or(non_finite)?).ok_or(overflow)?;
                 },
             }
         }
-        Some(stack)
+        Ok(stack)
     }
 
+    /// Space separated instructions, same as `Display`, which `Program::from_str` reads back
     pub fn render(&self) -> String{
-        self.instructions.iter().map(|inst| format!("{:?}", inst)).collect::<Vec<_>>().join(" ")
+        self.to_string()
     }
 
+    /// Fully parenthesized infix expression of the result, `None` if the program does not leave
+    /// a result or reads a constant that is not in `consts`
     pub fn render_pretty(&self, consts: &[f32]) -> Option<String>{
-        let mut stack = Vec::new();
-        enum Node{
-            Leaf(Instruction),
-            Node(Instruction, Vec<Node>)
-        }
-        impl Node{
-            pub fn to_string(&self, consts: &[f32]) -> String{
-                match self{
-                    Node::Leaf(instruction) => match instruction{
-                        Instruction::Const(ci) => format!("{}", consts[*ci as usize]),
-                        Instruction::Var(vi) => format!("v_{vi}"),
-                        _ => format!("{:?}", instruction)
-                    },
-                    Node::Node(i, c) => {
-                        match i{
-                            Instruction::Add => format!("({} + {})", c[1].to_string(consts), c[0].to_string(consts)),
-                            Instruction::Sub => format!("({} - {})", c[1].to_string(consts), c[0].to_string(consts)),
-                            Instruction::Mul => format!("({} * {})", c[1].to_string(consts), c[0].to_string(consts)),
-                            Instruction::Div => format!("({} / {})", c[1].to_string(consts), c[0].to_string(consts)),
-                            Instruction::Exp => format!("(e**{})", c[0].to_string(consts)),
-                            Instruction::Log => format!("(ln({}))", c[0].to_string(consts)),
-                            Instruction::Const(ci) => format!("{}", consts[*ci as usize]),
-                            Instruction::Var(vi) => format!("v_{vi}"),
-                        }
-                    },
-                }
-            }
-        }
-        for inst in self.instructions.iter().copied(){
-            match inst{
-                Instruction::Add => {
-                    let a = stack.pop()?;
-                    let b = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a,b]));
-                },
-                Instruction::Sub => {
-                    let a = stack.pop()?;
-                    let b = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a,b]));
-                },
-                Instruction::Mul => {
-                    let a = stack.pop()?;
-                    let b = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a,b]));
-                },
-                Instruction::Div => {
-                    let a = stack.pop()?;
-                    let b = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a,b]));
-                },
-                Instruction::Exp => {
-                    let a = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a]));
-                },
-                Instruction::Log => {
-                    let a = stack.pop()?;
-                    stack.push(Node::Node(inst, vec![a]));
-                },
-                Instruction::Const(_) | Instruction::Var(_) => {
-                    stack.push(Node::Leaf(inst))
-                }
+        Expr::from_program(self)?.render_pretty(consts)
+    }
+}
+
+// Same as `Debug`, e.g. `Add` or `Lit(2.5)`, literals print the shortest text that parses back to the same value
+impl Display for Instruction{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for Program{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        for (i, inst) in self.instructions.iter().enumerate(){
+            if i > 0{
+                write!(f, " ")?;
             }
+            write!(f, "{}", inst)?;
         }
+        Ok(())
+    }
+}
 
-        stack.pop().map(|n| n.to_string(consts))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalError{
+    /// The instruction at `index` needed more values than there were on the stack
+    StackUnderflow { index: usize },
+    /// The instruction at `index` pushed more than `STACKSIZE` values
+    StackOverflow { index: usize },
+    /// The instruction at `index` read a constant that was not provided
+    ConstOutOfRange { index: usize, const_index: u8 },
+    /// The instruction at `index` read a variable that was not provided
+    VarOutOfRange { index: usize, var_index: u8 },
+    /// The instruction at `index` produced a non-finite value under `Semantics::Checked`
+    NonFinite { index: usize },
+    /// The program finished without leaving a value on the stack
+    EmptyResult,
+    /// A batch column has a different length than the first column
+    ColumnLengthMismatch { column: usize },
+}
+
+impl Display for EvalError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        match self{
+            EvalError::StackUnderflow { index } => write!(f, "stack underflow at instruction {index}"),
+            EvalError::StackOverflow { index } => write!(f, "stack overflow at instruction {index}, the stack holds at most {STACKSIZE} values"),
+            EvalError::ConstOutOfRange { index, const_index } => write!(f, "instruction {index} reads Const({const_index}), which is out of range"),
+            EvalError::VarOutOfRange { index, var_index } => write!(f, "instruction {index} reads Var({var_index}), which is out of range"),
+            EvalError::NonFinite { index } => write!(f, "instruction {index} produced a non-finite value"),
+            EvalError::EmptyResult => write!(f, "program leaves no result on the stack"),
+            EvalError::ColumnLengthMismatch { column } => write!(f, "column {column} has a different length than the first column"),
+        }
     }
 }
 
-pub struct Stack{
-    values: [f32; STACKSIZE],
+impl std::error::Error for EvalError{}
+
+pub struct Stack<T = f32>{
+    values: [T; STACKSIZE],
     sp: usize
 }
 
-impl Stack{
+impl<T: Scalar> Default for Stack<T>{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl<T: Scalar> Stack<T>{
     pub fn new() -> Self{
-        Stack { values: [0f32; STACKSIZE], sp: 0 }
+        Stack { values: [T::ZERO; STACKSIZE], sp: 0 }
     }
 
     pub fn len(&self) -> usize{
         self.sp
     }
 
-    pub fn push(&mut self, value: f32){
-        self.values[self.sp] = value;
-        self.sp = (self.sp + 1).min(STACKSIZE - 1);
+    /// Pushes a value, returns `None` if the stack is already full
+    pub fn push(&mut self, value: T) -> Option<()>{
+        *self.values.get_mut(self.sp)? = value;
+        self.sp += 1;
+        Some(())
+    }
+
+    /// Pops the topmost `n` values, returning them in push order
+    pub fn pop_n(&mut self, n: usize) -> Option<&[T]>{
+        if self.sp < n{
+            None
+        }else{
+            self.sp -= n;
+            Some(&self.values[self.sp..self.sp + n])
+        }
     }
 
-    pub fn pop(&mut self) -> Option<f32>{
+    pub fn pop(&mut self) -> Option<T>{
         if self.sp == 0{
             None
         }else{
@@ -204,4 +380,153 @@ impl Stack{
             Some(self.values[self.sp])
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigonometric_and_power_instructions() {
+        let consts = [2.0, 9.0];
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Const(0),
+            Instruction::Pow,
+            Instruction::Var(0),
+            Instruction::Cos,
+            Instruction::Const(0),
+            Instruction::Pow,
+            Instruction::Add,
+        ]);
+        let result = program.evaluate_to_result(&consts, &[0.7]).unwrap();
+        assert!((result - 1.0).abs() < 1e-6);
+
+        let program = Program::create(&[
+            Instruction::Const(1),
+            Instruction::Neg,
+            Instruction::Abs,
+            Instruction::Sqrt,
+        ]);
+        assert_eq!(program.evaluate_to_result(&consts, &[]), Ok(3.0));
+        assert_eq!(program.render_pretty(&consts).unwrap(), "(sqrt((abs((-9)))))");
+    }
+
+    #[test]
+    fn test_stack_manipulation_instructions() {
+        let square = Program::create(&[Instruction::Var(0), Instruction::Dup, Instruction::Mul]);
+        assert_eq!(square.evaluate_to_result(&[], &[3.0]), Ok(9.0));
+        assert_eq!(square.render_pretty(&[]).unwrap(), "(v_0 * v_0)");
+
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Var(2),
+            Instruction::Rot,
+            Instruction::Over,
+            Instruction::Swap,
+            Instruction::Drop,
+            Instruction::Add,
+            Instruction::Div,
+        ]);
+        // v_0 v_1 v_2 -> v_1 v_2 v_0 -> v_1 v_2 v_0 v_2 -> v_1 v_2 v_2 v_0 -> v_1 v_2 v_2
+        assert_eq!(program.evaluate_to_result(&[], &[1.0, 2.0, 8.0]), Ok(0.125));
+        assert_eq!(program.render_pretty(&[]).unwrap(), "(v_1 / (v_2 + v_2))");
+        assert_eq!(Program::create(&[Instruction::Dup]).evaluate_to_result::<f32>(&[], &[]), Err(EvalError::StackUnderflow { index: 0 }));
+    }
+
+    #[test]
+    fn test_comparison_and_select_instructions() {
+        // ReLU: max(v_0, 0)
+        let relu = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Gt,
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Select,
+        ]);
+        assert_eq!(relu.evaluate_to_result(&[0.0], &[2.5]), Ok(2.5));
+        assert_eq!(relu.evaluate_to_result(&[0.0], &[-2.5]), Ok(0.0));
+        assert_eq!(relu.render_pretty(&[0.0]).unwrap(), "(v_0 if (v_0 > 0) else 0)");
+
+        let lt = Program::create(&[Instruction::Var(0), Instruction::Var(1), Instruction::Lt]);
+        assert_eq!(lt.evaluate_to_result(&[], &[1.0, 2.0]), Ok(1.0));
+        assert_eq!(lt.evaluate_to_result(&[], &[2.0, 1.0]), Ok(0.0));
+    }
+
+    #[test]
+    fn test_literal_constants() {
+        let program = Program::create(&[Instruction::Var(0), Instruction::Const(0), Instruction::Mul]);
+        let inlined = program.inline_consts(&[2.5]).unwrap();
+        assert_eq!(inlined.instructions, vec![Instruction::Var(0), Instruction::Lit(2.5), Instruction::Mul]);
+        assert_eq!(inlined.evaluate_to_result(&[], &[2.0]), Ok(5.0));
+        assert_eq!(inlined.render_pretty(&[]).unwrap(), "(v_0 * 2.5)");
+        assert!(program.inline_consts(&[]).is_none());
+        assert_ne!(Instruction::Lit(1.0), Instruction::Lit(2.0));
+    }
+
+    struct Clamp;
+
+    impl Operator for Clamp {
+        fn name(&self) -> &str {
+            "clamp"
+        }
+
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn eval(&self, args: &[f32]) -> f32 {
+            args[0].clamp(args[1], args[2])
+        }
+    }
+
+    static CLAMP: Clamp = Clamp;
+
+    #[test]
+    fn test_custom_operator() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Const(1),
+            Instruction::Custom(&CLAMP),
+        ]);
+        let consts = [-1.0, 1.0];
+        assert_eq!(program.evaluate_to_result(&consts, &[5.0]), Ok(1.0));
+        assert_eq!(program.evaluate_to_result(&consts, &[-0.5]), Ok(-0.5));
+        assert_eq!(program.render_pretty(&consts).unwrap(), "(clamp(v_0, -1, 1))");
+        assert_eq!(program.render(), "Var(0) Const(0) Const(1) Custom(clamp)");
+        assert_eq!(Program::create(&[Instruction::Custom(&CLAMP)]).evaluate_to_result(&consts, &[]), Err(EvalError::StackUnderflow { index: 0 }));
+    }
+
+    #[test]
+    fn test_double_precision_evaluation() {
+        let program = Program::create(&[
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Add,
+            Instruction::Const(0),
+            Instruction::Sub,
+            Instruction::Lit(0.5),
+            Instruction::Mul,
+        ]);
+        let result: f64 = program.evaluate_to_result(&[1e6], &[1e-3]).unwrap();
+        assert!((result - 0.5e-3).abs() < 1e-9);
+        assert_eq!(program.evaluate_to_result(&[1e6f32], &[1e-3]), Ok(0.0));
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let overflow = Program::create(&vec![Instruction::Lit(1.0); STACKSIZE + 1]);
+        assert_eq!(overflow.evaluate_to_result::<f32>(&[], &[]), Err(EvalError::StackOverflow { index: STACKSIZE }));
+        let full = Program::create(&vec![Instruction::Lit(1.0); STACKSIZE]);
+        assert_eq!(full.evaluate_to_result_and_remaining_stack::<f32>(&[], &[]), Ok((1.0, STACKSIZE - 1)));
+
+        let program = Program::create(&[Instruction::Const(1), Instruction::Var(2), Instruction::Add]);
+        assert_eq!(program.evaluate_to_result(&[1.0], &[0.0; 3]), Err(EvalError::ConstOutOfRange { index: 0, const_index: 1 }));
+        assert_eq!(program.evaluate_to_result(&[1.0, 2.0], &[0.0; 2]), Err(EvalError::VarOutOfRange { index: 1, var_index: 2 }));
+        assert_eq!(Program::new().evaluate_to_result::<f32>(&[], &[]), Err(EvalError::EmptyResult));
+    }
+}
@@ -0,0 +1,425 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::instructions::{EvalError, Instruction, Program, STACKSIZE};
+
+/// Closed interval `[lo, hi]` of values, the bounds may be infinite
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f32,
+    pub hi: f32,
+}
+
+impl Interval {
+    /// The whole real line
+    pub const ENTIRE: Interval = Interval {
+        lo: f32::NEG_INFINITY,
+        hi: f32::INFINITY,
+    };
+
+    pub fn new(lo: f32, hi: f32) -> Self {
+        Self {
+            lo: lo.min(hi),
+            hi: lo.max(hi),
+        }
+    }
+
+    /// Interval containing only `v`
+    pub fn point(v: f32) -> Self {
+        Self { lo: v, hi: v }
+    }
+
+    pub fn contains(&self, v: f32) -> bool {
+        self.lo <= v && v <= self.hi
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.lo.is_finite() && self.hi.is_finite()
+    }
+
+    /// Smallest interval containing both intervals
+    pub fn hull(&self, other: &Interval) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    fn from_bounds(bounds: [f32; 4]) -> Self {
+        Self {
+            lo: bounds.iter().copied().fold(f32::INFINITY, f32::min),
+            hi: bounds.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+
+    fn mul(&self, other: &Interval) -> Self {
+        // 0 * inf is 0 for interval bounds, as the infinite bound is never actually reached
+        let mul = |a: f32, b: f32| if a == 0.0 || b == 0.0 { 0.0 } else { a * b };
+        Self::from_bounds([
+            mul(self.lo, other.lo),
+            mul(self.lo, other.hi),
+            mul(self.hi, other.lo),
+            mul(self.hi, other.hi),
+        ])
+    }
+
+    /// Applies a function that is monotonically increasing on the interval
+    fn increasing(&self, f: impl Fn(f32) -> f32) -> Self {
+        Self {
+            lo: f(self.lo),
+            hi: f(self.hi),
+        }
+    }
+
+    fn sin(&self) -> Self {
+        if self.hi - self.lo >= 2.0 * PI || !self.is_finite() {
+            return Self::new(-1.0, 1.0);
+        }
+        let mut result = Self::new(self.lo.sin(), self.hi.sin());
+        // Maxima lie at pi/2 + 2k*pi, minima at -pi/2 + 2k*pi
+        let contains_shifted = |offset: f32| {
+            let k = ((self.lo - offset) / (2.0 * PI)).ceil();
+            self.contains(offset + k * 2.0 * PI)
+        };
+        if contains_shifted(FRAC_PI_2) {
+            result.hi = 1.0;
+        }
+        if contains_shifted(-FRAC_PI_2) {
+            result.lo = -1.0;
+        }
+        result
+    }
+
+    fn tan(&self) -> Self {
+        // Poles lie at pi/2 + k*pi
+        let k = ((self.lo - FRAC_PI_2) / PI).ceil();
+        if !self.is_finite() || self.contains(FRAC_PI_2 + k * PI) {
+            Self::ENTIRE
+        } else {
+            self.increasing(f32::tan)
+        }
+    }
+
+    fn integer_pow(&self, n: i32) -> Self {
+        if n % 2 == 0 && self.contains(0.0) {
+            let max = self.lo.abs().max(self.hi.abs());
+            Self::new(0.0, max.powi(n))
+        } else {
+            Self::new(self.lo.powi(n), self.hi.powi(n))
+        }
+    }
+}
+
+/// Kind of problem an operator can run into for some input within the input ranges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    DivisionByZero,
+    LogOfNonPositive,
+    SqrtOfNegative,
+    /// `Pow` with a negative base and a fractional exponent, or zero to a negative power
+    PowDomain,
+    /// The result can exceed the range of finite `f32` values
+    Overflow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DomainViolation {
+    /// Index of the instruction the violation occurs at
+    pub index: usize,
+    pub instruction: Instruction,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalReport {
+    /// Range the result of the program lies in
+    pub output: Interval,
+    pub violations: Vec<DomainViolation>,
+}
+
+impl IntervalReport {
+    /// Whether no operator can leave its domain or overflow for the given input ranges
+    pub fn is_safe(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Program {
+    /// Evaluates the program over intervals, `ranges[i]` being the range of `Var(i)`, and
+    /// collects every instruction that can divide by zero, leave the domain of `Log`, `Sqrt` or
+    /// `Pow`, or overflow for some input within the ranges.
+    ///
+    /// Violations are reported for plain IEEE arithmetic, the output interval contains every
+    /// result of `Semantics::Ieee` evaluation apart from rounding. Custom operators are assumed
+    /// to be able to produce any value, as nothing is known about them they are not reported as
+    /// overflowing.
+    ///
+    /// An `MCTS` evaluation function can return `None` for programs with violations to prune
+    /// unsafe branches from the search.
+    pub fn evaluate_interval(
+        &self,
+        consts: &[f32],
+        ranges: &[Interval],
+    ) -> Result<IntervalReport, EvalError> {
+        let mut stack: Vec<Interval> = Vec::new();
+        let mut violations = Vec::new();
+        for (index, inst) in self.instructions.iter().enumerate() {
+            let (pops, _) = inst.stack_effect();
+            if stack.len() < pops {
+                return Err(EvalError::StackUnderflow { index });
+            }
+            let mut violation = |kind| {
+                violations.push(DomainViolation {
+                    index,
+                    instruction: *inst,
+                    kind,
+                })
+            };
+            let operands = match inst.operator_arity() {
+                Some(arity) => stack.split_off(stack.len() - arity),
+                None => Vec::new(),
+            };
+            let inputs_finite = operands.iter().all(Interval::is_finite);
+            let result = match inst {
+                Instruction::Add => Interval::new(
+                    operands[0].lo + operands[1].lo,
+                    operands[0].hi + operands[1].hi,
+                ),
+                Instruction::Sub => Interval::new(
+                    operands[0].lo - operands[1].hi,
+                    operands[0].hi - operands[1].lo,
+                ),
+                Instruction::Mul => operands[0].mul(&operands[1]),
+                Instruction::Div => {
+                    let (a, b) = (operands[0], operands[1]);
+                    if b.contains(0.0) {
+                        violation(ViolationKind::DivisionByZero);
+                        Interval::ENTIRE
+                    } else {
+                        a.mul(&Interval::new(1.0 / b.hi, 1.0 / b.lo))
+                    }
+                }
+                Instruction::Pow => {
+                    let (a, b) = (operands[0], operands[1]);
+                    let integer_exponent = b.lo == b.hi && b.lo.fract() == 0.0;
+                    if integer_exponent && b.lo < 0.0 && a.contains(0.0) {
+                        violation(ViolationKind::PowDomain);
+                        Interval::ENTIRE
+                    } else if integer_exponent && b.lo.abs() <= i32::MAX as f32 {
+                        a.integer_pow(b.lo as i32)
+                    } else {
+                        if a.lo < 0.0 || (a.lo == 0.0 && b.lo < 0.0) {
+                            violation(ViolationKind::PowDomain);
+                        }
+                        if a.hi <= 0.0 {
+                            Interval::ENTIRE
+                        } else {
+                            // a^b = e^(b * ln(a)) on the positive part of the base
+                            let ln_a = Interval::new(a.lo.max(0.0).ln(), a.hi.ln());
+                            b.mul(&ln_a).increasing(f32::exp)
+                        }
+                    }
+                }
+                Instruction::Lt | Instruction::Gt => {
+                    let (a, b) = match inst {
+                        Instruction::Lt => (operands[0], operands[1]),
+                        _ => (operands[1], operands[0]),
+                    };
+                    if a.hi < b.lo {
+                        Interval::point(1.0)
+                    } else if a.lo >= b.hi {
+                        Interval::point(0.0)
+                    } else {
+                        Interval::new(0.0, 1.0)
+                    }
+                }
+                Instruction::Exp => operands[0].increasing(f32::exp),
+                Instruction::Log => {
+                    let v = operands[0];
+                    if v.lo <= 0.0 {
+                        violation(ViolationKind::LogOfNonPositive);
+                    }
+                    if v.hi <= 0.0 {
+                        Interval::ENTIRE
+                    } else {
+                        Interval::new(v.lo.max(0.0), v.hi).increasing(f32::ln)
+                    }
+                }
+                Instruction::Sqrt => {
+                    let v = operands[0];
+                    if v.lo < 0.0 {
+                        violation(ViolationKind::SqrtOfNegative);
+                    }
+                    if v.hi < 0.0 {
+                        Interval::ENTIRE
+                    } else {
+                        Interval::new(v.lo.max(0.0), v.hi).increasing(f32::sqrt)
+                    }
+                }
+                Instruction::Sin => operands[0].sin(),
+                Instruction::Cos => operands[0]
+                    .increasing(|v| v + FRAC_PI_2)
+                    .sin(),
+                Instruction::Tan => operands[0].tan(),
+                Instruction::Abs => {
+                    let v = operands[0];
+                    if v.contains(0.0) {
+                        Interval::new(0.0, v.lo.abs().max(v.hi))
+                    } else {
+                        Interval::new(v.lo.abs(), v.hi.abs())
+                    }
+                }
+                Instruction::Neg => Interval::new(-operands[0].hi, -operands[0].lo),
+                Instruction::Select => {
+                    let (c, t, f) = (operands[0], operands[1], operands[2]);
+                    if c == Interval::point(0.0) {
+                        f
+                    } else if !c.contains(0.0) {
+                        t
+                    } else {
+                        t.hull(&f)
+                    }
+                }
+                Instruction::Custom(_) => Interval::ENTIRE,
+                Instruction::Dup => *stack.last().unwrap(),
+                Instruction::Over => stack[stack.len() - 2],
+                Instruction::Swap => {
+                    let len = stack.len();
+                    stack.swap(len - 1, len - 2);
+                    continue;
+                }
+                Instruction::Drop => {
+                    stack.pop();
+                    continue;
+                }
+                Instruction::Rot => {
+                    let len = stack.len();
+                    stack[len - 3..].rotate_left(1);
+                    continue;
+                }
+                Instruction::Const(idx) => Interval::point(*consts.get(*idx as usize).ok_or(
+                    EvalError::ConstOutOfRange {
+                        index,
+                        const_index: *idx,
+                    },
+                )?),
+                Instruction::Var(idx) => *ranges.get(*idx as usize).ok_or(
+                    EvalError::VarOutOfRange {
+                        index,
+                        var_index: *idx,
+                    },
+                )?,
+                Instruction::Lit(v) => Interval::point(*v),
+            };
+            let reported = violations.last().is_some_and(|v| v.index == index);
+            if inst.operator_arity().is_some()
+                && !matches!(inst, Instruction::Custom(_))
+                && inputs_finite
+                && !result.is_finite()
+                && !reported
+            {
+                violations.push(DomainViolation {
+                    index,
+                    instruction: *inst,
+                    kind: ViolationKind::Overflow,
+                });
+            }
+            stack.push(result);
+            if stack.len() > STACKSIZE {
+                return Err(EvalError::StackOverflow { index });
+            }
+        }
+        let output = stack.pop().ok_or(EvalError::EmptyResult)?;
+        Ok(IntervalReport { output, violations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_output() {
+        // v_0 * v_0 - sin(v_1)
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Dup,
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Sin,
+            Instruction::Sub,
+        ]);
+        let report = program
+            .evaluate_interval(&[], &[Interval::new(-2.0, 1.0), Interval::new(0.0, PI)])
+            .unwrap();
+        assert!(report.is_safe());
+        // The interval product does not know both factors are the same value
+        assert_eq!(report.output, Interval::new(-3.0, 4.0));
+
+        let square = Program::create(&[Instruction::Var(0), Instruction::Lit(2.0), Instruction::Pow]);
+        let report = square.evaluate_interval(&[], &[Interval::new(-2.0, 1.0)]).unwrap();
+        assert_eq!(report.output, Interval::new(0.0, 4.0));
+    }
+
+    #[test]
+    fn test_interval_violations() {
+        // ln(v_0) / (v_1 - 1)
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Log,
+            Instruction::Var(1),
+            Instruction::Const(0),
+            Instruction::Sub,
+            Instruction::Div,
+        ]);
+        let safe = program
+            .evaluate_interval(&[1.0], &[Interval::new(1.0, 2.0), Interval::new(2.0, 3.0)])
+            .unwrap();
+        assert!(safe.is_safe());
+        let unsafe_report = program
+            .evaluate_interval(&[1.0], &[Interval::new(0.0, 2.0), Interval::new(0.0, 3.0)])
+            .unwrap();
+        let kinds = unsafe_report
+            .violations
+            .iter()
+            .map(|v| (v.index, v.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, ViolationKind::LogOfNonPositive),
+                (5, ViolationKind::DivisionByZero)
+            ]
+        );
+
+        let exp = Program::create(&[Instruction::Var(0), Instruction::Exp]);
+        let report = exp.evaluate_interval(&[], &[Interval::new(0.0, 100.0)]).unwrap();
+        assert_eq!(report.violations[0].kind, ViolationKind::Overflow);
+    }
+
+    #[test]
+    fn test_custom_operator_is_not_overflow() {
+        struct Tanh;
+
+        impl crate::Operator for Tanh {
+            fn name(&self) -> &'static str {
+                "tanh"
+            }
+
+            fn arity(&self) -> usize {
+                1
+            }
+
+            fn eval(&self, args: &[f32]) -> f32 {
+                args[0].tanh()
+            }
+        }
+
+        static TANH: Tanh = Tanh;
+        let program = Program::create(&[Instruction::Var(0), Instruction::Custom(&TANH)]);
+        let report = program
+            .evaluate_interval(&[], &[Interval::new(0.0, 1.0)])
+            .unwrap();
+        assert!(report.is_safe());
+        assert_eq!(report.output, Interval::ENTIRE);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    expr::Expr,
+    instructions::{Instruction, Program},
+    precedence::{
+        parenthesized, ATOM, COMPARISON, FUNCTION, POWER, PRODUCT, SUM, TERNARY as CASES,
+    },
+    render::RenderOptions,
+};
+
+const PARENS: [&str; 2] = ["\\left(", "\\right)"];
+
+fn number(v: f32, options: &RenderOptions) -> (String, u8) {
+    let (text, prec) = if v.is_infinite() {
+        ("\\infty".to_string(), ATOM)
+    } else {
+        match options.decimal(v.abs()) {
+            (mantissa, Some(exponent)) if mantissa == "1" => (format!("10^{{{exponent}}}"), POWER),
+            (mantissa, Some(exponent)) => {
+                (format!("{mantissa} \\times 10^{{{exponent}}}"), PRODUCT)
+            }
+            (text, None) => (text, ATOM),
+        }
+    };
+    if v.is_sign_negative() && !v.is_nan() {
+        (format!("-{text}"), SUM)
+    } else {
+        (text, prec)
+    }
+}
+
+/// Renders the tree and returns the binding strength of the result
+fn latex(expr: &Expr, consts: &[f32], options: &RenderOptions) -> Option<(String, u8)> {
+    let Expr::Op(inst, operands) = expr else {
+        return match expr {
+            Expr::Const(ci) => match options.const_names.get(ci) {
+                Some(name) => Some((name.clone(), ATOM)),
+                None => Some(number(*consts.get(*ci as usize)?, options)),
+            },
+            Expr::Var(vi) => match options.var_names.get(vi) {
+                Some(name) => Some((name.clone(), ATOM)),
+                None => Some((format!("v_{{{vi}}}"), ATOM)),
+            },
+            Expr::Lit(v) => Some(number(*v, options)),
+            Expr::Op(..) => unreachable!(),
+        };
+    };
+    let args = operands
+        .iter()
+        .map(|operand| latex(operand, consts, options))
+        .collect::<Option<Vec<_>>>()?;
+    let arg = |i: usize, min_prec: u8| parenthesized(args[i].clone(), min_prec, PARENS);
+    let function = |name: &str| (format!("{name}\\left({}\\right)", arg(0, CASES)), FUNCTION);
+    Some(match inst {
+        Instruction::Add => (format!("{} + {}", arg(0, SUM), arg(1, SUM + 1)), SUM),
+        Instruction::Sub => (format!("{} - {}", arg(0, SUM), arg(1, SUM + 1)), SUM),
+        Instruction::Mul => (
+            format!("{} \\cdot {}", arg(0, PRODUCT), arg(1, PRODUCT + 1)),
+            PRODUCT,
+        ),
+        Instruction::Div => (
+            format!("\\frac{{{}}}{{{}}}", arg(0, CASES), arg(1, CASES)),
+            FUNCTION,
+        ),
+        Instruction::Pow => (format!("{}^{{{}}}", arg(0, ATOM), arg(1, CASES)), POWER),
+        Instruction::Exp => (format!("e^{{{}}}", arg(0, CASES)), POWER),
+        Instruction::Lt => (
+            format!("{} < {}", arg(0, SUM), arg(1, SUM)),
+            COMPARISON,
+        ),
+        Instruction::Gt => (
+            format!("{} > {}", arg(0, SUM), arg(1, SUM)),
+            COMPARISON,
+        ),
+        Instruction::Neg => (format!("-{}", arg(0, PRODUCT)), SUM),
+        Instruction::Log => function("\\ln"),
+        Instruction::Sin => function("\\sin"),
+        Instruction::Cos => function("\\cos"),
+        Instruction::Tan => function("\\tan"),
+        Instruction::Sqrt => (format!("\\sqrt{{{}}}", arg(0, CASES)), ATOM),
+        Instruction::Abs => (format!("\\left|{}\\right|", arg(0, CASES)), ATOM),
+        Instruction::Select => (
+            format!(
+                "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+                arg(1, COMPARISON),
+                arg(0, CASES),
+                arg(2, COMPARISON)
+            ),
+            CASES,
+        ),
+        Instruction::Custom(op) => {
+            let args = (0..operands.len())
+                .map(|i| arg(i, CASES))
+                .collect::<Vec<_>>();
+            (
+                format!(
+                    "\\operatorname{{{}}}\\left({}\\right)",
+                    op.name(),
+                    args.join(", ")
+                ),
+                FUNCTION,
+            )
+        }
+        _ => unreachable!("{inst:?} is not an operator"),
+    })
+}
+
+impl Program {
+    /// Renders the program as a LaTeX math expression, using only the parentheses the precedence
+    /// of the operators requires. Returns `None` if the program does not leave a result or reads
+    /// a constant that is not in `consts`.
+    pub fn render_latex(&self, consts: &[f32]) -> Option<String> {
+        self.render_latex_with(consts, &RenderOptions::default())
+    }
+
+    /// Same as `Program::render_latex`, with the names and number formatting of `options`. Names
+    /// are inserted as they are, so they can be any LaTeX, e.g. `\alpha` or `\mathrm{time}`.
+    pub fn render_latex_with(&self, consts: &[f32], options: &RenderOptions) -> Option<String> {
+        latex(&Expr::from_program(self)?, consts, options).map(|(text, _)| text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Program, RenderOptions};
+
+    fn latex(insts: &[Instruction], consts: &[f32]) -> String {
+        Program::create(insts).render_latex(consts).unwrap()
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        // (v_0 * 2) / ln(v_1)
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Lit(2.0),
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Log,
+            Instruction::Div,
+        ];
+        assert_eq!(
+            latex(&insts, &[]),
+            "\\frac{v_{0} \\cdot 2}{\\ln\\left(v_{1}\\right)}"
+        );
+        // (v_0 - (v_1 + c_0)) * e**(v_0 * v_1)
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Const(0),
+            Instruction::Add,
+            Instruction::Sub,
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Mul,
+            Instruction::Exp,
+            Instruction::Mul,
+        ];
+        assert_eq!(
+            latex(&insts, &[-1.5]),
+            "\\left(v_{0} - \\left(v_{1} + \\left(-1.5\\right)\\right)\\right) \\cdot e^{v_{0} \\cdot v_{1}}"
+        );
+        // (-v_0)**2 + -(v_1**2)
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Neg,
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Var(1),
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Neg,
+            Instruction::Add,
+        ];
+        assert_eq!(
+            latex(&insts, &[]),
+            "\\left(-v_{0}\\right)^{2} + \\left(-v_{1}^{2}\\right)"
+        );
+    }
+
+    #[test]
+    fn test_power_of_function() {
+        // (v_0 / c_0)**2 * sin(v_0)**2
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Div,
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Mul,
+        ];
+        assert_eq!(
+            latex(&insts, &[3.0]),
+            "\\left(\\frac{v_{0}}{3}\\right)^{2} \\cdot \\left(\\sin\\left(v_{0}\\right)\\right)^{2}"
+        );
+        assert_eq!(Program::create(&insts).render_latex(&[]), None);
+    }
+
+    #[test]
+    fn test_exponent_notation() {
+        // c_0 * v_0 + c_1 * c_2
+        let insts = [
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Const(1),
+            Instruction::Const(2),
+            Instruction::Mul,
+            Instruction::Add,
+        ];
+        let consts = [6.02214e23, 1.2345e-10, -1e20];
+        assert_eq!(
+            latex(&insts, &consts),
+            "6.02214 \\times 10^{23} \\cdot v_{0} + 1.2345 \\times 10^{-10} \\cdot \\left(-10^{20}\\right)"
+        );
+        let options = RenderOptions::default()
+            .with_significant_digits(2)
+            .with_var_name(0, "n");
+        assert_eq!(
+            Program::create(&insts).render_latex_with(&consts, &options),
+            Some(
+                "6 \\times 10^{23} \\cdot n + 1.2 \\times 10^{-10} \\cdot \\left(-10^{20}\\right)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Lit(0.0),
+            Instruction::Lt,
+            Instruction::Var(0),
+            Instruction::Neg,
+            Instruction::Var(0),
+            Instruction::Sqrt,
+            Instruction::Select,
+            Instruction::Lit(1.0),
+            Instruction::Add,
+        ];
+        assert_eq!(
+            latex(&insts, &[]),
+            "\\left(\\begin{cases} -v_{0} & \\text{if } v_{0} < 0 \\\\ \\sqrt{v_{0}} & \\text{otherwise} \\end{cases}\\right) + 1"
+        );
+    }
+}
@@ -1,6 +1,40 @@
-pub use instructions::{Instruction, Program};
-pub use nodes::MCTS;
+pub use analysis::{StackReport, ValidationError};
+pub use canonical::CanonicalProgram;
+pub use compile::CompiledProgram;
+pub use expr::{Expr, Subtrees};
+pub use gradient::Gradient;
+pub use instructions::{EvalError, Instruction, Program, STACKSIZE};
+pub use interval::{DomainViolation, Interval, IntervalReport, ViolationKind};
+pub use nodes::{SearchResult, MCTS};
+pub use operator::Operator;
+pub use optimize::{ConstantFitter, ConstantFitting, FitResult, Loss};
+pub use parse::ParseError;
+pub use render::RenderOptions;
+pub use scalar::Scalar;
+pub use semantics::Semantics;
+pub use simd::LANES;
 
+mod analysis;
 mod arena;
+mod batch;
+mod canonical;
+mod compile;
+mod dot;
+mod export;
+mod expr;
+mod gradient;
 mod instructions;
+mod interval;
+mod latex;
 mod nodes;
+mod operator;
+mod optimize;
+mod parse;
+mod precedence;
+mod render;
+mod scalar;
+mod semantics;
+#[cfg(feature = "serde")]
+mod serialize;
+mod simd;
+mod simplify;
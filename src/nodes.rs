@@ -3,6 +3,7 @@ use core::f32;
 use crate::{
     arena::{Ap, Arena},
     instructions::{Instruction, Program},
+    optimize::ConstantFitting,
 };
 
 #[derive(Clone)]
@@ -33,15 +34,30 @@ impl ProgramNode {
     }
 }
 
+/// A found program together with the constants it was scored with
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResult {
+    pub program: Program,
+    pub consts: Vec<f32>,
+    pub score: f32,
+}
+
+type EvaluationFunc = Box<dyn Fn(&Program) -> Option<f32>>;
+
 pub struct MCTS {
     iset: Vec<Instruction>,
     root_node: Ap<ProgramNode>,
     pub exploration_chance: f32,
     pub max_program_length: usize,
     arena: Arena<ProgramNode>,
-    evaluation_func: Box<dyn Fn(&Program) -> Option<f32>>,
+    evaluation_func: EvaluationFunc,
     pub best_node: Ap<ProgramNode>,
     current_program: Program,
+    /// If set, the constants of promising programs are fitted before they are scored
+    pub constant_fitting: Option<ConstantFitting>,
+    /// Fitted constants of the best node, if its score was achieved with fitted constants
+    best_consts: Option<Vec<f32>>,
 }
 
 impl MCTS {
@@ -69,6 +85,8 @@ impl MCTS {
             arena,
             evaluation_func: Box::new(evaluate),
             best_node: root_node,
+            constant_fitting: None,
+            best_consts: None,
         }
     }
 
@@ -78,7 +96,7 @@ impl MCTS {
 
     pub fn node_memory_upper_bound(&self) -> usize {
         let prog_node_size = std::mem::size_of::<ProgramNode>();
-        let average_child_count = (self.exploration_chance * (self.iset.len() as f32));
+        let average_child_count = self.exploration_chance * (self.iset.len() as f32);
         let average_children_size = (prog_node_size as f32 * average_child_count).ceil() as usize;
         average_children_size * self.node_count() //The nodes are already contained within the child size
     }
@@ -95,6 +113,30 @@ impl MCTS {
         self.make_program(&self.best_node)
     }
 
+    /// Fitted constants of the best program, `None` if its score did not come from constant fitting
+    pub fn best_consts(&self) -> Option<&[f32]> {
+        self.best_consts.as_deref()
+    }
+
+    /// The best program with its fitted constants inlined as literals, if it has any
+    pub fn make_best_fitted_program(&self) -> Program {
+        let program = self.make_best_program();
+        self.best_consts
+            .as_ref()
+            .and_then(|consts| program.inline_consts(consts))
+            .unwrap_or(program)
+    }
+
+    /// The best program and its score, with the fitted constants if constant fitting produced the
+    /// score and `consts` otherwise. `None` if no program has been scored yet.
+    pub fn best_result(&self, consts: &[f32]) -> Option<SearchResult> {
+        Some(SearchResult {
+            program: self.make_best_program(),
+            consts: self.best_consts().unwrap_or(consts).to_vec(),
+            score: self.high_score()?,
+        })
+    }
+
     pub fn make_program(&self, node: &Ap<ProgramNode>) -> Program {
         let mut instructions = Vec::new();
         let mut current_node = *node;
@@ -113,13 +155,6 @@ impl MCTS {
     }
 
     fn recalculate_score_recursive(&mut self, node: &Ap<ProgramNode>) {
-        let child_count = node
-            .get(&self.arena)
-            .children
-            .iter()
-            .filter_map(|c| c.get(&self.arena).self_score)
-            .count()
-            .min(1);
         // Mean
         //let child_score = node.get(&self.arena).children.iter().map(|c| c.get(&self.arena).self_score).filter(|s| s.is_finite()).sum::<f32>() / child_count as f32;
         // Max
@@ -170,8 +205,17 @@ impl MCTS {
         //Add the new instruction to the program
         self.current_program.push_inst(*new_inst);
         // Simulation step
-        let score =
+        let mut score =
             (self.evaluation_func)(&self.current_program).and_then(|v| v.is_finite().then_some(v));
+        let mut fitted_consts = None;
+        if let Some(fitting) = &mut self.constant_fitting {
+            if let Some((fitted_score, consts)) =
+                fitting.refine(&self.current_program, score, &self.evaluation_func)
+            {
+                score = Some(fitted_score);
+                fitted_consts = Some(consts);
+            }
+        }
         //Insert into tree
         let mut new_node = ProgramNode::new(*new_inst, score);
         //new_node.program = program.clone();
@@ -180,6 +224,7 @@ impl MCTS {
         node.get_mut(&mut self.arena).children.push(new_node_ap);
         if score > self.high_score() {
             self.best_node = new_node_ap;
+            self.best_consts = fitted_consts;
         }
         // Backpropagation step
         if score.is_some() {
@@ -193,14 +238,14 @@ impl MCTS {
 
         fn convert_node(
             old_arena: &Arena<ProgramNode>,
-            mut new_arena: &mut Arena<ProgramNode>,
+            new_arena: &mut Arena<ProgramNode>,
             old_node: Ap<ProgramNode>,
             new_parent: Option<Ap<ProgramNode>>,
             old_best_node: Ap<ProgramNode>,
             new_best_node: &mut Option<Ap<ProgramNode>>,
             minimum_visits: u64
         ) -> Ap<ProgramNode> {
-            let old_node_inst = old_node.get(&old_arena);
+            let old_node_inst = old_node.get(old_arena);
             let mut new_inst = old_node_inst.clone();
             if let Some(new_parent) = new_parent {
                 new_inst.parent = Some(new_parent);
@@ -212,7 +257,7 @@ impl MCTS {
                 *new_best_node = Some(new_node);
             }
             for i in 0..child_count {
-                let child = new_node.get(&new_arena).children[i];
+                let child = new_node.get(new_arena).children[i];
                 let new_child = convert_node(
                     old_arena,
                     new_arena,
@@ -222,7 +267,7 @@ impl MCTS {
                     new_best_node,
                     minimum_visits
                 );
-                new_node.get_mut(&mut new_arena).children[i] = new_child;
+                new_node.get_mut(new_arena).children[i] = new_child;
             }
             new_node
         }
@@ -250,11 +295,11 @@ impl MCTS {
         }
 
         //Check if we are at the end of a branch
-        if current_depth == self.max_program_length - 1 {
-            if node.get(&self.arena).children.len() == self.iset.len() {
-                node.get_mut(&mut self.arena).done = true;
-                return false;
-            }
+        if current_depth == self.max_program_length - 1
+            && node.get(&self.arena).children.len() == self.iset.len()
+        {
+            node.get_mut(&mut self.arena).done = true;
+            return false;
         }
 
         //Extend program with current instruction
@@ -312,7 +357,7 @@ impl MCTS {
                 for i in 0..total_node_count {
                     if i != chosen_index {
                         self.current_program.truncate_to_len(current_program_length);
-                        let node = node.get(&self.arena).children[i].clone();
+                        let node = node.get(&self.arena).children[i];
                         if self.search_step(current_depth + 1, &node) {
                             return true;
                         }
@@ -380,6 +425,8 @@ impl MCTS {
 
 #[cfg(test)]
 mod tests {
+    use crate::{ConstantFitting, Instruction, Program, MCTS};
+
     fn softmax(v: &[f32]) -> Vec<f32> {
         let max_score = v
             .iter()
@@ -402,4 +449,40 @@ mod tests {
             .zip(expected)
             .all(|(c, e)| close(*c, e)));
     }
+
+    #[test]
+    fn test_search_with_constant_fitting() {
+        fastrand::seed(7);
+        let x = (0..16).map(|i| i as f32 * 0.25).collect::<Vec<_>>();
+        let y = x.iter().map(|x| 3.7 * x + 0.2).collect::<Vec<_>>();
+        let (data_x, data_y) = (x.clone(), y.clone());
+        let score = move |prog: &Program| {
+            let predictions = prog.evaluate_batch(&[1.0, 1.0], &[&data_x]).ok()?;
+            let error = predictions
+                .iter()
+                .zip(data_y.iter())
+                .map(|(p, y)| (p - y) * (p - y))
+                .sum::<f32>();
+            Some(-error / data_y.len() as f32)
+        };
+        let iset = [
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Const(1),
+            Instruction::Mul,
+            Instruction::Add,
+        ];
+        let mut mcts = MCTS::with_max_program_length(&iset, 8, score);
+        mcts.constant_fitting = Some(ConstantFitting::new(&[1.0, 1.0], vec![x], y));
+        for _ in 0..500 {
+            mcts.search_one();
+        }
+        assert!(mcts.high_score().unwrap() > -1e-6);
+        assert!(mcts.best_consts().is_some());
+        let fitted = mcts.make_best_fitted_program();
+        assert!(fitted
+            .instructions
+            .iter()
+            .all(|inst| !matches!(inst, Instruction::Const(_))));
+    }
 }
@@ -0,0 +1,70 @@
+use std::fmt::Debug;
+
+/// A user-defined operator that can be used in programs via `Instruction::Custom`.
+///
+/// Operators are identified by their name, so two operators with the same name and arity are
+/// considered the same instruction.
+///
+/// ```
+/// use evofunc::{Instruction, Operator, Program};
+///
+/// struct Tanh;
+///
+/// impl Operator for Tanh {
+///     fn name(&self) -> &str {
+///         "tanh"
+///     }
+///
+///     fn arity(&self) -> usize {
+///         1
+///     }
+///
+///     fn eval(&self, args: &[f32]) -> f32 {
+///         args[0].tanh()
+///     }
+/// }
+///
+/// static TANH: Tanh = Tanh;
+///
+/// let program = Program::create(&[Instruction::Var(0), Instruction::Custom(&TANH)]);
+/// assert_eq!(program.evaluate_to_result(&[], &[0.0]), Ok(0.0));
+/// assert_eq!(program.render_pretty(&[]).unwrap(), "(tanh(v_0))");
+/// ```
+pub trait Operator: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Number of values popped from the stack
+    fn arity(&self) -> usize;
+
+    /// Evaluates the operator, `args` are in push order (the top of the stack is last)
+    fn eval(&self, args: &[f32]) -> f32;
+
+    /// Evaluates the operator in double precision, by default this falls back to `eval`
+    fn eval_f64(&self, args: &[f64]) -> f64 {
+        let args = args.iter().map(|v| *v as f32).collect::<Vec<_>>();
+        self.eval(&args) as f64
+    }
+
+    /// Partial derivative with respect to `args[i]`, used by `Program::evaluate_gradient`.
+    /// By default this is approximated with a central finite difference of `eval_f64`.
+    fn derivative(&self, args: &[f64], i: usize) -> f64 {
+        let h = 1e-3 * args[i].abs().max(1.0);
+        let mut shifted = args.to_vec();
+        shifted[i] = args[i] + h;
+        let upper = self.eval_f64(&shifted);
+        shifted[i] = args[i] - h;
+        let lower = self.eval_f64(&shifted);
+        (upper - lower) / (2.0 * h)
+    }
+
+    /// Renders the operator for `Program::render_pretty`, `args` are in push order
+    fn render(&self, args: &[String]) -> String {
+        format!("({}({}))", self.name(), args.join(", "))
+    }
+}
+
+impl Debug for dyn Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{
+    instructions::{EvalError, Instruction, Program},
+    semantics::Semantics,
+};
+
+/// Loss minimized by `ConstantFitter` over the residuals `prediction - target`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loss {
+    /// Mean of the squared residuals
+    SquaredError,
+    /// Squared error for residuals up to the given size and absolute error above it, which makes
+    /// the fit robust against outliers
+    Huber(f32),
+}
+
+impl Loss {
+    fn value(&self, residual: f64) -> f64 {
+        match self {
+            Loss::SquaredError => residual * residual,
+            Loss::Huber(delta) => {
+                let delta = *delta as f64;
+                if residual.abs() <= delta {
+                    residual * residual
+                } else {
+                    2.0 * delta * residual.abs() - delta * delta
+                }
+            }
+        }
+    }
+
+    /// Weight of a residual in the iteratively reweighted least squares problem
+    fn weight(&self, residual: f64) -> f64 {
+        match self {
+            Loss::SquaredError => 1.0,
+            Loss::Huber(delta) => {
+                let delta = *delta as f64;
+                if residual.abs() <= delta {
+                    1.0
+                } else {
+                    delta / residual.abs()
+                }
+            }
+        }
+    }
+
+    fn mean(&self, residuals: &[f64]) -> f64 {
+        residuals.iter().map(|r| self.value(*r)).sum::<f64>() / residuals.len().max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitResult {
+    pub consts: Vec<f32>,
+    /// Loss with the fitted constants
+    pub loss: f32,
+    pub iterations: usize,
+}
+
+/// Tunes the `Const` values of a program to a dataset with the Levenberg-Marquardt algorithm,
+/// using the derivatives from `Program::evaluate_gradient`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConstantFitter {
+    pub loss: Loss,
+    pub max_iterations: usize,
+    /// Fitting stops once an iteration improves the loss by less than this fraction
+    pub tolerance: f32,
+    pub semantics: Semantics,
+}
+
+impl Default for ConstantFitter {
+    fn default() -> Self {
+        Self::new(Loss::SquaredError)
+    }
+}
+
+struct Linearization {
+    residuals: Vec<f64>,
+    /// Derivative of every residual with respect to every constant
+    jacobian: Vec<Vec<f64>>,
+}
+
+impl ConstantFitter {
+    pub fn new(loss: Loss) -> Self {
+        Self {
+            loss,
+            max_iterations: 100,
+            tolerance: 1e-9,
+            semantics: Semantics::default(),
+        }
+    }
+
+    fn linearize(
+        &self,
+        program: &Program,
+        consts: &[f64],
+        rows: &[Vec<f64>],
+        targets: &[f32],
+    ) -> Result<Linearization, EvalError> {
+        let mut residuals = Vec::with_capacity(rows.len());
+        let mut jacobian = Vec::with_capacity(rows.len());
+        for (row, target) in rows.iter().zip(targets.iter()) {
+            let gradient = program.evaluate_gradient_with(self.semantics, consts, row)?;
+            residuals.push(gradient.value - *target as f64);
+            jacobian.push(gradient.d_consts);
+        }
+        Ok(Linearization {
+            residuals,
+            jacobian,
+        })
+    }
+
+    /// Fits the constants of `program` so its results on the column-major dataset `columns`
+    /// match `targets`, starting from `initial_consts`. Fails if the program cannot be evaluated
+    /// with the initial constants, steps that make the program fail are rejected.
+    pub fn fit(
+        &self,
+        program: &Program,
+        initial_consts: &[f32],
+        columns: &[&[f32]],
+        targets: &[f32],
+    ) -> Result<FitResult, EvalError> {
+        if let Some(column) = columns.iter().position(|c| c.len() != targets.len()) {
+            return Err(EvalError::ColumnLengthMismatch { column });
+        }
+        let rows = (0..targets.len())
+            .map(|r| columns.iter().map(|c| c[r] as f64).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut consts = initial_consts.iter().map(|c| *c as f64).collect::<Vec<_>>();
+        let n = consts.len();
+        let mut current = self.linearize(program, &consts, &rows, targets)?;
+        let mut loss = self.loss.mean(&current.residuals);
+        let mut lambda = 1e-3;
+        let mut iterations = 0;
+        let used = used_consts(program, n);
+        while iterations < self.max_iterations && loss > 0.0 && used.iter().any(|u| *u) {
+            iterations += 1;
+            // Normal equations of the weighted least squares problem
+            let mut jtj = vec![vec![0.0; n]; n];
+            let mut jtr = vec![0.0; n];
+            for (residual, derivatives) in current.residuals.iter().zip(current.jacobian.iter()) {
+                let weight = self.loss.weight(*residual);
+                for i in 0..n {
+                    jtr[i] += weight * derivatives[i] * residual;
+                    for j in 0..n {
+                        jtj[i][j] += weight * derivatives[i] * derivatives[j];
+                    }
+                }
+            }
+            let mut improved = None;
+            while lambda < 1e12 {
+                let mut damped = jtj.clone();
+                for (i, row) in damped.iter_mut().enumerate() {
+                    // Unused constants get a unit diagonal so the system stays solvable
+                    row[i] += if used[i] { lambda * row[i].max(1e-12) } else { 1.0 };
+                }
+                let candidate = solve(damped, jtr.clone()).map(|step| {
+                    consts
+                        .iter()
+                        .zip(step.iter())
+                        .map(|(c, s)| c - s)
+                        .collect::<Vec<_>>()
+                });
+                let result = candidate.and_then(|candidate| {
+                    let next = self.linearize(program, &candidate, &rows, targets).ok()?;
+                    let next_loss = self.loss.mean(&next.residuals);
+                    (next_loss.is_finite() && next_loss < loss)
+                        .then_some((candidate, next, next_loss))
+                });
+                if let Some(result) = result {
+                    lambda = (lambda / 10.0).max(1e-12);
+                    improved = Some(result);
+                    break;
+                }
+                lambda *= 10.0;
+            }
+            let Some((candidate, next, next_loss)) = improved else {
+                break;
+            };
+            let improvement = (loss - next_loss) / loss;
+            consts = candidate;
+            current = next;
+            loss = next_loss;
+            if improvement < self.tolerance as f64 {
+                break;
+            }
+        }
+        Ok(FitResult {
+            consts: consts.iter().map(|c| *c as f32).collect(),
+            loss: loss as f32,
+            iterations,
+        })
+    }
+}
+
+fn used_consts(program: &Program, n: usize) -> Vec<bool> {
+    let mut used = vec![false; n];
+    for inst in program.instructions.iter() {
+        if let Instruction::Const(idx) = inst {
+            if let Some(u) = used.get_mut(*idx as usize) {
+                *u = true;
+            }
+        }
+    }
+    used
+}
+
+/// Solves `a * x = b` with Gaussian elimination and partial pivoting
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (v, p) in a[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                *v -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = (row + 1..n).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x.iter().all(|v| v.is_finite()).then_some(x)
+}
+
+/// Settings for fitting constants inside `MCTS`, see `MCTS::constant_fitting`
+#[derive(Debug, Clone)]
+pub struct ConstantFitting {
+    pub fitter: ConstantFitter,
+    /// Constants the fit starts from
+    pub initial_consts: Vec<f32>,
+    /// Column-major dataset, `columns[i]` holds the values of `Var(i)`
+    pub columns: Vec<Vec<f32>>,
+    pub targets: Vec<f32>,
+    /// Only nodes scoring at least this with the initial constants are considered promising
+    /// enough to be fitted
+    pub min_score: f32,
+    /// Only nodes whose score with the initial constants ranks among the `top_k` best such scores
+    /// seen so far are fitted
+    pub top_k: usize,
+    /// Best scores with the initial constants seen so far, in descending order
+    top_scores: Vec<f32>,
+}
+
+impl ConstantFitting {
+    pub fn new(initial_consts: &[f32], columns: Vec<Vec<f32>>, targets: Vec<f32>) -> Self {
+        Self {
+            fitter: ConstantFitter::default(),
+            initial_consts: initial_consts.to_vec(),
+            columns,
+            targets,
+            min_score: f32::NEG_INFINITY,
+            top_k: 16,
+            top_scores: Vec::new(),
+        }
+    }
+
+    /// Records `score` if it ranks among the `top_k` best scores so far
+    fn is_top_k(&mut self, score: f32) -> bool {
+        let rank = self.top_scores.partition_point(|s| *s >= score);
+        if rank >= self.top_k {
+            return false;
+        }
+        self.top_scores.insert(rank, score);
+        self.top_scores.truncate(self.top_k);
+        true
+    }
+
+    /// Fits the constants of a promising program and scores the program with the fitted
+    /// constants inlined, returns the new score and constants if they improve on `score`
+    pub(crate) fn refine(
+        &mut self,
+        program: &Program,
+        score: Option<f32>,
+        evaluate: impl Fn(&Program) -> Option<f32>,
+    ) -> Option<(f32, Vec<f32>)> {
+        let score = score?;
+        let uses_consts = program
+            .instructions
+            .iter()
+            .any(|inst| matches!(inst, Instruction::Const(_)));
+        if score < self.min_score || !uses_consts || !self.is_top_k(score) {
+            return None;
+        }
+        let columns = self.columns.iter().map(|c| c.as_slice()).collect::<Vec<_>>();
+        let fit = self
+            .fitter
+            .fit(program, &self.initial_consts, &columns, &self.targets)
+            .ok()?;
+        let fitted_score = evaluate(&program.inline_consts(&fit.consts)?)?;
+        (fitted_score.is_finite() && fitted_score > score).then_some((fitted_score, fit.consts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_linear_model() {
+        // c_0 * v_0 + c_1
+        let program = Program::create(&[
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Const(1),
+            Instruction::Add,
+        ]);
+        let x = (0..20).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+        let y = x.iter().map(|x| 2.5 * x - 1.0).collect::<Vec<_>>();
+        let fit = ConstantFitter::default()
+            .fit(&program, &[1.0, 0.0], &[&x], &y)
+            .unwrap();
+        assert!((fit.consts[0] - 2.5).abs() < 1e-4);
+        assert!((fit.consts[1] + 1.0).abs() < 1e-4);
+        assert!(fit.loss < 1e-8);
+    }
+
+    #[test]
+    fn test_fit_nonlinear_model_with_outlier() {
+        // c_0 * exp(c_1 * v_0), unused Const(2)
+        let program = Program::create(&[
+            Instruction::Const(0),
+            Instruction::Const(1),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Exp,
+            Instruction::Mul,
+        ]);
+        let x = (0..30).map(|i| i as f32 * 0.1).collect::<Vec<_>>();
+        let mut y = x.iter().map(|x| 0.5 * (1.3 * x).exp()).collect::<Vec<_>>();
+        y[10] += 50.0;
+        let fitter = ConstantFitter::new(Loss::Huber(0.1));
+        let fit = fitter.fit(&program, &[1.0, 1.0, 7.0], &[&x], &y).unwrap();
+        assert!((fit.consts[0] - 0.5).abs() < 1e-2);
+        assert!((fit.consts[1] - 1.3).abs() < 1e-2);
+        assert_eq!(fit.consts[2], 7.0);
+    }
+
+    #[test]
+    fn test_refine_skips_low_scores() {
+        // c_0 * v_0
+        let program =
+            Program::create(&[Instruction::Const(0), Instruction::Var(0), Instruction::Mul]);
+        let x = vec![1.0, 2.0, 3.0];
+        let y = x.iter().map(|x| 2.0 * x).collect::<Vec<_>>();
+        let mut fitting = ConstantFitting::new(&[1.0], vec![x], y);
+        fitting.top_k = 2;
+        let fits = std::cell::Cell::new(0);
+        let evaluate = |_: &Program| {
+            fits.set(fits.get() + 1);
+            Some(0.0)
+        };
+        assert!(fitting.refine(&program, Some(-2.0), evaluate).is_some());
+        assert!(fitting.refine(&program, Some(-3.0), evaluate).is_some());
+        // Ranks below the two best scores so far
+        assert!(fitting.refine(&program, Some(-4.0), evaluate).is_none());
+        assert_eq!(fits.get(), 2);
+        assert!(fitting.refine(&program, Some(-1.0), evaluate).is_some());
+        fitting.min_score = 0.0;
+        assert!(fitting.refine(&program, Some(-0.5), evaluate).is_none());
+        assert_eq!(fits.get(), 3);
+    }
+}
@@ -0,0 +1,696 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    expr::Expr,
+    instructions::{Instruction, Program},
+    operator::Operator,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A character at byte offset `position` that does not start a token
+    UnexpectedChar { position: usize, found: char },
+    /// A token at byte offset `position` that does not fit the grammar
+    UnexpectedToken {
+        position: usize,
+        found: String,
+        expected: &'static str,
+    },
+    /// The input ended in the middle of an expression
+    UnexpectedEnd { expected: &'static str },
+    /// A name that is neither a variable, a function nor a custom operator
+    UnknownIdentifier { position: usize, name: String },
+    /// A function called with the wrong number of arguments
+    WrongArgumentCount {
+        position: usize,
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { position, found } => {
+                write!(f, "unexpected character '{found}' at {position}")
+            }
+            ParseError::UnexpectedToken {
+                position,
+                found,
+                expected,
+            } => write!(f, "expected {expected} at {position}, found '{found}'"),
+            ParseError::UnexpectedEnd { expected } => {
+                write!(f, "expected {expected}, found end of input")
+            }
+            ParseError::UnknownIdentifier { position, name } => {
+                write!(f, "unknown identifier '{name}' at {position}")
+            }
+            ParseError::WrongArgumentCount {
+                position,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "'{name}' at {position} takes {expected} arguments, but {found} were given"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(v) => write!(f, "{v}"),
+            Token::Ident(name) => write!(f, "{name}"),
+            Token::Symbol(symbol) => write!(f, "{symbol}"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 10] = ["**", "+", "-", "*", "/", "<", ">", "(", ")", ","];
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut rest = src.char_indices().peekable();
+    while let Some(&(position, c)) = rest.peek() {
+        if c.is_whitespace() {
+            rest.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = position;
+            let mut previous = ' ';
+            while let Some(&(i, c)) = rest.peek() {
+                let exponent_sign = (c == '+' || c == '-') && (previous == 'e' || previous == 'E');
+                let exponent = (c == 'e' || c == 'E')
+                    && src[i + 1..]
+                        .trim_start_matches(['+', '-'])
+                        .starts_with(|c: char| c.is_ascii_digit());
+                if !(c.is_ascii_digit() || c == '.' || exponent || exponent_sign) {
+                    break;
+                }
+                previous = c;
+                end = i + c.len_utf8();
+                rest.next();
+            }
+            let text = &src[position..end];
+            let v = text.parse().map_err(|_| ParseError::UnexpectedToken {
+                position,
+                found: text.to_string(),
+                expected: "a number",
+            })?;
+            tokens.push((position, Token::Number(v)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = position;
+            while let Some(&(i, c)) = rest.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                rest.next();
+            }
+            tokens.push((position, Token::Ident(src[position..end].to_string())));
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| src[position..].starts_with(**s))
+                .ok_or(ParseError::UnexpectedChar { position, found: c })?;
+            for _ in 0..symbol.len() {
+                rest.next();
+            }
+            tokens.push((position, Token::Symbol(symbol)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser with Python's operator precedence
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    var_names: &'a [&'a str],
+    operators: &'a [&'static dyn Operator],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map(|(p, _)| *p).unwrap_or(0)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn eat_ident(&mut self, name: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Ident(i)) if i == name);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        match self.tokens.get(self.next) {
+            Some((position, token)) => ParseError::UnexpectedToken {
+                position: *position,
+                found: token.to_string(),
+                expected,
+            },
+            None => ParseError::UnexpectedEnd { expected },
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(symbol))
+        }
+    }
+
+    /// `then if cond else otherwise`
+    fn ternary(&mut self) -> Result<Expr, ParseError> {
+        let then = self.comparison()?;
+        if !self.eat_ident("if") {
+            return Ok(then);
+        }
+        let cond = self.comparison()?;
+        if !self.eat_ident("else") {
+            return Err(self.unexpected("else"));
+        }
+        let otherwise = self.ternary()?;
+        Ok(Expr::Op(Instruction::Select, vec![cond, then, otherwise]))
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let a = self.additive()?;
+        let inst = if self.eat("<") {
+            Instruction::Lt
+        } else if self.eat(">") {
+            Instruction::Gt
+        } else {
+            return Ok(a);
+        };
+        let b = self.additive()?;
+        Ok(Expr::Op(inst, vec![a, b]))
+    }
+
+    fn additive(&mut self) -> Result<Expr, ParseError> {
+        let mut a = self.term()?;
+        loop {
+            let inst = if self.eat("+") {
+                Instruction::Add
+            } else if self.eat("-") {
+                Instruction::Sub
+            } else {
+                return Ok(a);
+            };
+            a = Expr::Op(inst, vec![a, self.term()?]);
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut a = self.unary()?;
+        loop {
+            let inst = if self.eat("*") {
+                Instruction::Mul
+            } else if self.eat("/") {
+                Instruction::Div
+            } else {
+                return Ok(a);
+            };
+            a = Expr::Op(inst, vec![a, self.unary()?]);
+        }
+    }
+
+    /// Negation binds weaker than `**`, a negated number literal becomes a negative literal
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if !self.eat("-") {
+            return self.power();
+        }
+        match self.unary()? {
+            Expr::Lit(v) => Ok(Expr::Lit(-v)),
+            v => Ok(Expr::Op(Instruction::Neg, vec![v])),
+        }
+    }
+
+    /// Right associative `**`, with `e**x` parsed as `Exp`
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let euler = matches!(self.peek(), Some(Token::Ident(i)) if i == "e")
+            && !self.var_names.contains(&"e");
+        let base = self.primary()?;
+        if !self.eat("**") {
+            return Ok(base);
+        }
+        let exponent = self.unary()?;
+        if euler {
+            Ok(Expr::Op(Instruction::Exp, vec![exponent]))
+        } else {
+            Ok(Expr::Op(Instruction::Pow, vec![base, exponent]))
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let position = self.position();
+        match self.peek().cloned() {
+            Some(Token::Number(v)) => {
+                self.next += 1;
+                Ok(Expr::Lit(v))
+            }
+            Some(Token::Symbol("(")) => {
+                self.next += 1;
+                let inner = self.ternary()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Ident(name)) => {
+                self.next += 1;
+                if self.eat("(") {
+                    self.call(position, name)
+                } else {
+                    self.name(position, name)
+                }
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    fn name(&self, position: usize, name: String) -> Result<Expr, ParseError> {
+        if let Some(idx) = self.var_names.iter().position(|n| *n == name) {
+            return Ok(Expr::Var(idx as u8));
+        }
+        match name.as_str() {
+            "e" => Ok(Expr::Lit(std::f32::consts::E)),
+            "inf" => Ok(Expr::Lit(f32::INFINITY)),
+            "NaN" => Ok(Expr::Lit(f32::NAN)),
+            _ => name
+                .strip_prefix("v_")
+                .and_then(|idx| idx.parse().ok())
+                .map(Expr::Var)
+                .ok_or(ParseError::UnknownIdentifier { position, name }),
+        }
+    }
+
+    fn call(&mut self, position: usize, name: String) -> Result<Expr, ParseError> {
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.ternary()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let inst = match name.as_str() {
+            "exp" => Instruction::Exp,
+            "ln" | "log" => Instruction::Log,
+            "sin" => Instruction::Sin,
+            "cos" => Instruction::Cos,
+            "tan" => Instruction::Tan,
+            "sqrt" => Instruction::Sqrt,
+            "abs" => Instruction::Abs,
+            _ => self
+                .operators
+                .iter()
+                .find(|op| op.name() == name)
+                .map(|op| Instruction::Custom(*op))
+                .ok_or_else(|| ParseError::UnknownIdentifier {
+                    position,
+                    name: name.clone(),
+                })?,
+        };
+        let arity = inst.operator_arity().unwrap();
+        if args.len() != arity {
+            return Err(ParseError::WrongArgumentCount {
+                position,
+                name,
+                expected: arity,
+                found: args.len(),
+            });
+        }
+        Ok(Expr::Op(inst, args))
+    }
+}
+
+impl Program {
+    /// Parses an infix expression in the syntax of `Program::render_pretty`, with variables
+    /// written as `v_0`, `v_1`, ... and numbers compiled to `Lit` instructions
+    pub fn parse_infix(src: &str) -> Result<Program, ParseError> {
+        Self::parse_infix_with(src, &[], &[])
+    }
+
+    /// Parses an infix expression where `var_names[i]` also refers to `Var(i)` and the custom
+    /// `operators` can be called by their name
+    pub fn parse_infix_with(
+        src: &str,
+        var_names: &[&str],
+        operators: &[&'static dyn Operator],
+    ) -> Result<Program, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            next: 0,
+            var_names,
+            operators,
+        };
+        let expr = parser.ternary()?;
+        if parser.next < parser.tokens.len() {
+            return Err(parser.unexpected("an operator or end of input"));
+        }
+        Ok(expr.to_program_unchecked())
+    }
+}
+
+/// Instructions without an argument, matched by their `Display` name
+const NAMED: [Instruction; 21] = [
+    Instruction::Add,
+    Instruction::Sub,
+    Instruction::Mul,
+    Instruction::Div,
+    Instruction::Exp,
+    Instruction::Log,
+    Instruction::Sin,
+    Instruction::Cos,
+    Instruction::Tan,
+    Instruction::Pow,
+    Instruction::Sqrt,
+    Instruction::Abs,
+    Instruction::Neg,
+    Instruction::Lt,
+    Instruction::Gt,
+    Instruction::Select,
+    Instruction::Dup,
+    Instruction::Swap,
+    Instruction::Drop,
+    Instruction::Over,
+    Instruction::Rot,
+];
+
+impl ParseError {
+    /// Moves the position of the error by `offset` bytes
+    fn shifted(self, offset: usize) -> Self {
+        match self {
+            ParseError::UnexpectedChar { position, found } => ParseError::UnexpectedChar {
+                position: position + offset,
+                found,
+            },
+            ParseError::UnexpectedToken {
+                position,
+                found,
+                expected,
+            } => ParseError::UnexpectedToken {
+                position: position + offset,
+                found,
+                expected,
+            },
+            ParseError::UnknownIdentifier { position, name } => ParseError::UnknownIdentifier {
+                position: position + offset,
+                name,
+            },
+            ParseError::WrongArgumentCount {
+                position,
+                name,
+                expected,
+                found,
+            } => ParseError::WrongArgumentCount {
+                position: position + offset,
+                name,
+                expected,
+                found,
+            },
+            ParseError::UnexpectedEnd { expected } => ParseError::UnexpectedEnd { expected },
+        }
+    }
+}
+
+impl Instruction {
+    /// Parses a single instruction in its `Display` form, `Custom(name)` is looked up by name in
+    /// `operators`
+    pub fn parse_with(src: &str, operators: &[&'static dyn Operator]) -> Result<Self, ParseError> {
+        let Some((name, rest)) = src.split_once('(') else {
+            return NAMED
+                .iter()
+                .find(|inst| inst.to_string() == src)
+                .copied()
+                .ok_or_else(|| ParseError::UnknownIdentifier {
+                    position: 0,
+                    name: src.to_string(),
+                });
+        };
+        let position = name.len() + 1;
+        let Some(arg) = rest.strip_suffix(')') else {
+            return Err(ParseError::UnexpectedToken {
+                position,
+                found: rest.to_string(),
+                expected: "an argument followed by ')'",
+            });
+        };
+        let invalid = |expected| ParseError::UnexpectedToken {
+            position,
+            found: arg.to_string(),
+            expected,
+        };
+        match name {
+            "Const" => arg
+                .parse()
+                .map(Instruction::Const)
+                .map_err(|_| invalid("a constant index from 0 to 255")),
+            "Var" => arg
+                .parse()
+                .map(Instruction::Var)
+                .map_err(|_| invalid("a variable index from 0 to 255")),
+            "Lit" => arg
+                .parse()
+                .map(Instruction::Lit)
+                .map_err(|_| invalid("a number")),
+            "Custom" => operators
+                .iter()
+                .find(|op| op.name() == arg)
+                .map(|op| Instruction::Custom(*op))
+                .ok_or_else(|| ParseError::UnknownIdentifier {
+                    position,
+                    name: arg.to_string(),
+                }),
+            _ => Err(ParseError::UnknownIdentifier {
+                position: 0,
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &[])
+    }
+}
+
+impl Program {
+    /// Parses the whitespace separated instructions written by `Program::render`, with `Custom`
+    /// instructions resolved from `operators`
+    pub fn parse_with(src: &str, operators: &[&'static dyn Operator]) -> Result<Self, ParseError> {
+        let mut program = Program::new();
+        for token in src.split_whitespace() {
+            let offset = token.as_ptr() as usize - src.as_ptr() as usize;
+            let inst =
+                Instruction::parse_with(token, operators).map_err(|e| e.shifted(offset))?;
+            program.push_inst(inst);
+        }
+        Ok(program)
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Operator, ParseError, Program};
+
+    struct Hypot;
+
+    impl Operator for Hypot {
+        fn name(&self) -> &'static str {
+            "hypot"
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn eval(&self, args: &[f32]) -> f32 {
+            args[0].hypot(args[1])
+        }
+    }
+
+    static HYPOT: Hypot = Hypot;
+
+    #[test]
+    fn test_precedence() {
+        let program = Program::parse_infix("(v_0 * 2) / ln(v_1)").unwrap();
+        assert_eq!(program.render(), "Var(0) Lit(2.0) Mul Var(1) Log Div");
+        let error = Program::parse_infix("-x**2 + 3 * y ** 2 ** 0.5 - 1e-3").err();
+        assert_eq!(
+            error,
+            Some(ParseError::UnknownIdentifier {
+                position: 1,
+                name: "x".to_string()
+            })
+        );
+        let program =
+            Program::parse_infix_with("-x**2 + 3 * y ** 2 ** 0.5 - 1e-3", &["x", "y"], &[])
+                .unwrap();
+        assert_eq!(
+            program.render(),
+            "Var(0) Lit(2.0) Pow Neg Lit(3.0) Var(1) Lit(2.0) Lit(0.5) Pow Pow Mul Add Lit(0.001) Sub"
+        );
+        let program =
+            Program::parse_infix_with("1 if x < 0 else hypot(x, e**x)", &["x"], &[&HYPOT]).unwrap();
+        assert_eq!(
+            program.render(),
+            "Var(0) Lit(0.0) Lt Lit(1.0) Var(0) Var(0) Exp Custom(hypot) Select"
+        );
+    }
+
+    #[test]
+    fn test_render_pretty_round_trip() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Pow,
+            Instruction::Var(1),
+            Instruction::Neg,
+            Instruction::Exp,
+            Instruction::Sub,
+            Instruction::Var(0),
+            Instruction::Lit(-1.5),
+            Instruction::Gt,
+            Instruction::Var(1),
+            Instruction::Sqrt,
+            Instruction::Abs,
+            Instruction::Rot,
+            Instruction::Select,
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Cos,
+            Instruction::Tan,
+            Instruction::Div,
+            Instruction::Var(1),
+            Instruction::Var(0),
+            Instruction::Custom(&HYPOT),
+            Instruction::Log,
+            Instruction::Mul,
+            Instruction::Const(1),
+            Instruction::Var(0),
+            Instruction::Lit(2.0),
+            Instruction::Mul,
+            Instruction::Pow,
+            Instruction::Add,
+        ]);
+        let consts = [2.5, -2.0];
+        let rendered = program.render_pretty(&consts).unwrap();
+        let parsed = Program::parse_infix_with(&rendered, &[], &[&HYPOT]).unwrap();
+        assert_eq!(parsed.render_pretty(&[]).unwrap(), rendered);
+        for (x, y) in [(0.5, 2.0), (-1.0, 3.0), (4.0, -0.25)] {
+            assert_eq!(
+                parsed.evaluate_to_result::<f32>(&[], &[x, y]),
+                program.evaluate_to_result(&consts, &[x, y])
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Program::parse_infix("v_0 + $"),
+            Err(ParseError::UnexpectedChar {
+                position: 6,
+                found: '$'
+            })
+        );
+        assert_eq!(
+            Program::parse_infix("(v_0 + 1"),
+            Err(ParseError::UnexpectedEnd { expected: ")" })
+        );
+        assert_eq!(
+            Program::parse_infix("v_0 v_1"),
+            Err(ParseError::UnexpectedToken {
+                position: 4,
+                found: "v_1".to_string(),
+                expected: "an operator or end of input"
+            })
+        );
+        assert_eq!(
+            Program::parse_infix("sin(v_0, v_1)")
+                .unwrap_err()
+                .to_string(),
+            "'sin' at 0 takes 1 arguments, but 2 were given"
+        );
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Lit(0.1),
+            Instruction::Lit(-1e-7),
+            Instruction::Lit(f32::INFINITY),
+            Instruction::Const(255),
+            Instruction::Rot,
+            Instruction::Custom(&HYPOT),
+            Instruction::Select,
+            Instruction::Dup,
+            Instruction::Drop,
+        ]);
+        let rendered = program.to_string();
+        assert_eq!(rendered, program.render());
+        assert_eq!(Program::parse_with(&rendered, &[&HYPOT]), Ok(program));
+        assert_eq!("Lit(2.5)".parse(), Ok(Instruction::Lit(2.5)));
+        assert_eq!("".parse(), Ok(Program::new()));
+
+        assert_eq!(
+            rendered.parse::<Program>().unwrap_err().to_string(),
+            "unknown identifier 'hypot' at 58"
+        );
+        assert_eq!(
+            "Var(0)  Const(256) Add".parse::<Program>(),
+            Err(ParseError::UnexpectedToken {
+                position: 14,
+                found: "256".to_string(),
+                expected: "a constant index from 0 to 255"
+            })
+        );
+        assert_eq!(
+            "Var(0) Lit(1.0 Add".parse::<Program>().unwrap_err().to_string(),
+            "expected an argument followed by ')' at 11, found '1.0'"
+        );
+        assert_eq!(
+            "Var(0) Mull".parse::<Program>().unwrap_err().to_string(),
+            "unknown identifier 'Mull' at 7"
+        );
+    }
+}
@@ -0,0 +1,26 @@
+// Binding strength of rendered infix forms, following Python's operator precedence so rendered
+// programs can be read back with `Program::parse_infix_with`. An operand is parenthesized when it
+// binds weaker than its position requires.
+pub(crate) const TERNARY: u8 = 0;
+pub(crate) const COMPARISON: u8 = 1;
+pub(crate) const SUM: u8 = 2;
+pub(crate) const PRODUCT: u8 = 3;
+pub(crate) const UNARY: u8 = 4;
+pub(crate) const POWER: u8 = 5;
+/// Fractions and function applications, which still need parentheses as the base of a power in
+/// LaTeX
+pub(crate) const FUNCTION: u8 = 6;
+pub(crate) const ATOM: u8 = 7;
+
+/// Wraps the rendered operand in the `[open, close]` delimiters if it binds weaker than `min_prec`
+pub(crate) fn parenthesized(
+    (text, prec): (String, u8),
+    min_prec: u8,
+    [open, close]: [&str; 2],
+) -> String {
+    if prec < min_prec {
+        format!("{open}{text}{close}")
+    } else {
+        text
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use crate::{
+    expr::Expr,
+    instructions::{Instruction, Program},
+    precedence::{parenthesized, ATOM, COMPARISON, POWER, PRODUCT, SUM, TERNARY, UNARY},
+};
+
+/// Names and number formatting for `Program::render_infix` and `Program::render_latex_with`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Names of variables by index, unnamed variables are rendered as `v_0`, `v_1`, ...
+    pub var_names: HashMap<u8, String>,
+    /// Names of constants by index, unnamed constants are rendered as their value
+    pub const_names: HashMap<u8, String>,
+    /// Number of significant digits of rendered numbers, `None` renders the shortest text that
+    /// parses back to the same `f32`
+    pub significant_digits: Option<usize>,
+}
+
+impl RenderOptions {
+    pub fn with_var_name(mut self, idx: u8, name: impl Into<String>) -> Self {
+        self.var_names.insert(idx, name.into());
+        self
+    }
+
+    pub fn with_const_name(mut self, idx: u8, name: impl Into<String>) -> Self {
+        self.const_names.insert(idx, name.into());
+        self
+    }
+
+    pub fn with_significant_digits(mut self, digits: usize) -> Self {
+        self.significant_digits = Some(digits);
+        self
+    }
+
+    /// Text of `v` rounded to `significant_digits`, with the decimal exponent split off for very
+    /// large and very small magnitudes
+    pub(crate) fn decimal(&self, v: f32) -> (String, Option<String>) {
+        // Like Python, very large and very small magnitudes are printed with an exponent
+        let positional = v == 0.0 || !v.is_finite() || (1e-4..1e16).contains(&v.abs());
+        let text = match self.significant_digits {
+            Some(digits) if v.is_finite() && v != 0.0 => {
+                // Round in scientific notation, then print the shortest form of the rounded value
+                let rounded = format!("{:.*e}", digits.max(1) - 1, v as f64);
+                let rounded = rounded.parse::<f64>().unwrap();
+                if positional {
+                    format!("{rounded}")
+                } else {
+                    format!("{rounded:e}")
+                }
+            }
+            _ if positional => format!("{v}"),
+            _ => format!("{v:e}"),
+        };
+        match text.split_once('e') {
+            Some((mantissa, exponent)) => (mantissa.to_string(), Some(exponent.to_string())),
+            None => (text, None),
+        }
+    }
+
+    fn number(&self, v: f32) -> (String, u8) {
+        let text = match self.decimal(v) {
+            (mantissa, Some(exponent)) => format!("{mantissa}e{exponent}"),
+            (text, None) => text,
+        };
+        if text.starts_with('-') {
+            (text, UNARY)
+        } else {
+            (text, ATOM)
+        }
+    }
+}
+
+const PARENS: [&str; 2] = ["(", ")"];
+
+/// Renders the tree and returns the precedence of the result
+fn infix(expr: &Expr, consts: &[f32], options: &RenderOptions) -> Option<(String, u8)> {
+    let (inst, operands) = match expr {
+        Expr::Const(ci) => {
+            return match options.const_names.get(ci) {
+                Some(name) => Some((name.clone(), ATOM)),
+                None => Some(options.number(*consts.get(*ci as usize)?)),
+            };
+        }
+        Expr::Var(vi) => {
+            return Some(match options.var_names.get(vi) {
+                Some(name) => (name.clone(), ATOM),
+                None => (format!("v_{vi}"), ATOM),
+            });
+        }
+        Expr::Lit(v) => return Some(options.number(*v)),
+        Expr::Op(inst, operands) => (inst, operands),
+    };
+    let args = operands
+        .iter()
+        .map(|operand| infix(operand, consts, options))
+        .collect::<Option<Vec<_>>>()?;
+    let arg = |i: usize, min_prec: u8| parenthesized(args[i].clone(), min_prec, PARENS);
+    // Left associative operators need the right operand parenthesized at the same precedence
+    let binary = |op: &str, prec: u8| (format!("{} {op} {}", arg(0, prec), arg(1, prec + 1)), prec);
+    let function = |name: &str| (format!("{name}({})", arg(0, TERNARY)), ATOM);
+    Some(match inst {
+        Instruction::Add => binary("+", SUM),
+        Instruction::Sub => binary("-", SUM),
+        Instruction::Mul => binary("*", PRODUCT),
+        Instruction::Div => binary("/", PRODUCT),
+        // Comparisons chain in Python, so neither side may be a comparison
+        Instruction::Lt => (format!("{} < {}", arg(0, SUM), arg(1, SUM)), COMPARISON),
+        Instruction::Gt => (format!("{} > {}", arg(0, SUM), arg(1, SUM)), COMPARISON),
+        Instruction::Pow => (format!("{}**{}", arg(0, ATOM), arg(1, UNARY)), POWER),
+        Instruction::Exp => (format!("e**{}", arg(0, UNARY)), POWER),
+        Instruction::Neg => (format!("-{}", arg(0, POWER)), UNARY),
+        Instruction::Log => function("ln"),
+        Instruction::Sin => function("sin"),
+        Instruction::Cos => function("cos"),
+        Instruction::Tan => function("tan"),
+        Instruction::Sqrt => function("sqrt"),
+        Instruction::Abs => function("abs"),
+        Instruction::Select => (
+            format!(
+                "{} if {} else {}",
+                arg(1, COMPARISON),
+                arg(0, COMPARISON),
+                arg(2, TERNARY)
+            ),
+            TERNARY,
+        ),
+        Instruction::Custom(op) => {
+            let args = (0..args.len()).map(|i| arg(i, TERNARY)).collect::<Vec<_>>();
+            (op.render(&args), ATOM)
+        }
+        _ => unreachable!("{inst:?} is not an operator"),
+    })
+}
+
+impl Program {
+    /// Renders the program in the syntax of `Program::render_pretty`, but only with the
+    /// parentheses operator precedence and associativity require, and with the names and number
+    /// formatting of `options`.
+    ///
+    /// Returns `None` if the program does not leave a result or reads an unnamed constant that is
+    /// not in `consts`.
+    pub fn render_infix(&self, consts: &[f32], options: &RenderOptions) -> Option<String> {
+        infix(&Expr::from_program(self)?, consts, options).map(|(text, _)| text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Program, RenderOptions};
+
+    #[test]
+    fn test_minimal_parentheses() {
+        // v_0 - (v_1 - (2 * v_0) / (c_0 + 1)) ** -v_1
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Lit(2.0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Const(0),
+            Instruction::Lit(1.0),
+            Instruction::Add,
+            Instruction::Div,
+            Instruction::Sub,
+            Instruction::Var(1),
+            Instruction::Neg,
+            Instruction::Pow,
+            Instruction::Sub,
+        ]);
+        let options = RenderOptions::default();
+        let rendered = program.render_infix(&[0.5], &options).unwrap();
+        assert_eq!(rendered, "v_0 - (v_1 - 2 * v_0 / (0.5 + 1))**-v_1");
+        // The rendering parses back to the same tree
+        let parsed = Program::parse_infix(&rendered).unwrap();
+        assert_eq!(
+            parsed.render_pretty(&[]),
+            program.inline_consts(&[0.5]).unwrap().render_pretty(&[])
+        );
+
+        // -(v_0**2) if v_0 < v_1 else (v_0 + v_1) * ln(v_0)
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Lt,
+            Instruction::Var(0),
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Neg,
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Add,
+            Instruction::Var(0),
+            Instruction::Log,
+            Instruction::Mul,
+            Instruction::Select,
+        ]);
+        assert_eq!(
+            program.render_infix(&[], &options).unwrap(),
+            "-v_0**2 if v_0 < v_1 else (v_0 + v_1) * ln(v_0)"
+        );
+    }
+
+    #[test]
+    fn test_symbols_and_digits() {
+        // 0.5 * c_2 * t**2 + c_0 * t - 1234.5678
+        let program = Program::create(&[
+            Instruction::Const(1),
+            Instruction::Const(2),
+            Instruction::Mul,
+            Instruction::Var(0),
+            Instruction::Lit(2.0),
+            Instruction::Pow,
+            Instruction::Mul,
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Add,
+            Instruction::Lit(-1234.5678),
+            Instruction::Add,
+        ]);
+        let consts = [1.2345679, 0.5, 9.81];
+        let options = RenderOptions::default()
+            .with_var_name(0, "time")
+            .with_const_name(2, "g");
+        assert_eq!(
+            program.render_infix(&consts, &options).unwrap(),
+            "0.5 * g * time**2 + 1.2345679 * time + -1234.5677"
+        );
+        let options = options.with_significant_digits(3);
+        assert_eq!(
+            program.render_infix(&consts, &options).unwrap(),
+            "0.5 * g * time**2 + 1.23 * time + -1230"
+        );
+        assert_eq!(program.render_infix(&consts[..1], &options), None);
+    }
+
+    #[test]
+    fn test_exponent_notation() {
+        // c_0 * v_0 + c_1
+        let program = Program::create(&[
+            Instruction::Const(0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Const(1),
+            Instruction::Add,
+        ]);
+        let consts = [1.2345e-10, -6.02214e23];
+        let options = RenderOptions::default();
+        let rendered = program.render_infix(&consts, &options).unwrap();
+        assert_eq!(rendered, "1.2345e-10 * v_0 + -6.02214e23");
+        assert_eq!(
+            Program::parse_infix(&rendered).unwrap(),
+            program.inline_consts(&consts).unwrap()
+        );
+        let options = options.with_significant_digits(2);
+        assert_eq!(
+            program.render_infix(&consts, &options).unwrap(),
+            "1.2e-10 * v_0 + -6e23"
+        );
+        assert_eq!(
+            program.render_infix(&[0.00012345, 1e15], &options).unwrap(),
+            "0.00012 * v_0 + 1000000000000000"
+        );
+    }
+}
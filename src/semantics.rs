@@ -0,0 +1,175 @@
+use crate::scalar::Scalar;
+
+/// How operators treat inputs outside of their domain and results that overflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Semantics {
+    /// Plain IEEE 754 arithmetic, e.g. `x / 0` is infinite and `ln(-1)` is NaN
+    Ieee,
+    /// Classic genetic programming protected operators:
+    /// `x / 0` is `1`, `ln` and `sqrt` work on the absolute value with `ln(0) = 0`,
+    /// `Pow` uses the absolute value of negative bases with fractional exponents and overflowing
+    /// `Exp`/`Pow` results saturate
+    #[default]
+    Protected,
+    /// IEEE 754 arithmetic, but an operator producing a non-finite value fails the evaluation with
+    /// `EvalError::NonFinite`
+    Checked,
+}
+
+impl Semantics {
+    /// Fails values that are not allowed as operator results under these semantics
+    pub(crate) fn check<T: Scalar>(self, v: T) -> Option<T> {
+        if self == Semantics::Checked && !v.is_finite() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+}
+
+/// Clamps infinite values to the largest finite value of the same sign, NaN is kept
+pub(crate) fn saturate<T: Scalar>(v: T) -> T {
+    if v > T::MAX {
+        T::MAX
+    } else if v < -T::MAX {
+        -T::MAX
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalError, Instruction, Program, Semantics};
+
+    fn eval(insts: &[Instruction], semantics: Semantics, x: f32) -> Option<f32> {
+        let program = Program::create(insts);
+        let row = program.evaluate_to_result_with(semantics, &[], &[x]);
+        let batch = program
+            .evaluate_batch_with(semantics, &[], &[&[x]])
+            .map(|v| v[0]);
+        let simd = program
+            .evaluate_batch_simd_with(semantics, &[], &[&[x]])
+            .map(|v| v[0]);
+        let compiled = program
+            .compile_with::<f32>(semantics)
+            .unwrap()
+            .evaluate(&[], &[x]);
+        for other in [batch, simd, compiled] {
+            assert_eq!(row.map(f32::to_bits), other.map(f32::to_bits));
+        }
+        row.ok()
+    }
+
+    const DIV: [Instruction; 3] = [Instruction::Lit(3.0), Instruction::Var(0), Instruction::Div];
+    const LOG: [Instruction; 2] = [Instruction::Var(0), Instruction::Log];
+    const SQRT: [Instruction; 2] = [Instruction::Var(0), Instruction::Sqrt];
+    const EXP: [Instruction; 2] = [Instruction::Var(0), Instruction::Exp];
+    const POW: [Instruction; 3] = [Instruction::Var(0), Instruction::Lit(0.5), Instruction::Pow];
+
+    #[test]
+    fn test_ieee_semantics() {
+        let s = Semantics::Ieee;
+        assert_eq!(eval(&DIV, s, 0.0), Some(f32::INFINITY));
+        assert!(eval(&LOG, s, -1.0).unwrap().is_nan());
+        assert_eq!(eval(&LOG, s, 0.0), Some(f32::NEG_INFINITY));
+        assert_eq!(eval(&LOG, s, 1.0), Some(0.0));
+        assert!(eval(&SQRT, s, -4.0).unwrap().is_nan());
+        assert_eq!(eval(&EXP, s, 100.0), Some(f32::INFINITY));
+        assert!(eval(&POW, s, -4.0).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_protected_semantics() {
+        let s = Semantics::Protected;
+        assert_eq!(eval(&DIV, s, 0.0), Some(1.0));
+        assert_eq!(eval(&DIV, s, 2.0), Some(1.5));
+        assert_eq!(eval(&LOG, s, -1.0), Some(0.0));
+        assert_eq!(eval(&LOG, s, 0.0), Some(0.0));
+        assert_eq!(eval(&LOG, s, -4.0), Some(4f32.ln()));
+        assert_eq!(eval(&SQRT, s, -4.0), Some(2.0));
+        assert_eq!(eval(&EXP, s, 100.0), Some(f32::MAX));
+        assert_eq!(eval(&POW, s, -4.0), Some(2.0));
+        let cube = [Instruction::Var(0), Instruction::Lit(3.0), Instruction::Pow];
+        assert_eq!(eval(&cube, s, -2.0), Some(-8.0));
+        assert_eq!(eval(&cube, s, 1e20), Some(f32::MAX));
+        let program = Program::create(&LOG);
+        assert_eq!(
+            program.evaluate_to_result(&[], &[8.0]),
+            program.evaluate_to_result_with(s, &[], &[8.0])
+        );
+    }
+
+    #[test]
+    fn test_checked_semantics() {
+        let s = Semantics::Checked;
+        assert_eq!(eval(&DIV, s, 0.0), None);
+        assert_eq!(eval(&DIV, s, 2.0), Some(1.5));
+        assert_eq!(eval(&LOG, s, -1.0), None);
+        assert_eq!(eval(&LOG, s, 0.0), None);
+        assert_eq!(eval(&LOG, s, 1.0), Some(0.0));
+        assert_eq!(eval(&SQRT, s, -4.0), None);
+        assert_eq!(eval(&EXP, s, 100.0), None);
+        assert_eq!(eval(&POW, s, 4.0), Some(2.0));
+        assert_eq!(eval(&POW, s, -4.0), None);
+        assert_eq!(
+            Program::create(&DIV).evaluate_to_result_with(s, &[], &[0.0]),
+            Err(EvalError::NonFinite { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_dropped_values_are_evaluated() {
+        let paths = |insts: &[Instruction], semantics: Semantics| {
+            let program = Program::create(insts);
+            [
+                program.evaluate_to_result_with(semantics, &[], &[2.0]),
+                program
+                    .evaluate_batch_with(semantics, &[], &[&[2.0]])
+                    .map(|v| v[0]),
+                program
+                    .evaluate_batch_simd_with(semantics, &[], &[&[2.0]])
+                    .map(|v| v[0]),
+                program
+                    .compile_with::<f32>(semantics)
+                    .unwrap()
+                    .evaluate(&[], &[2.0]),
+            ]
+        };
+        let log = [
+            Instruction::Lit(-1.0),
+            Instruction::Log,
+            Instruction::Drop,
+            Instruction::Var(0),
+        ];
+        for result in paths(&log, Semantics::Checked) {
+            assert_eq!(result, Err(EvalError::NonFinite { index: 1 }));
+        }
+        for result in paths(&log, Semantics::Protected) {
+            assert_eq!(result, Ok(2.0));
+        }
+        let out_of_range = [Instruction::Const(3), Instruction::Drop, Instruction::Var(0)];
+        for result in paths(&out_of_range, Semantics::Protected) {
+            assert_eq!(
+                result,
+                Err(EvalError::ConstOutOfRange {
+                    index: 0,
+                    const_index: 3
+                })
+            );
+        }
+        // Shared values are computed once and reported at their own instruction
+        let shared = [
+            Instruction::Var(0),
+            Instruction::Lit(0.0),
+            Instruction::Div,
+            Instruction::Dup,
+            Instruction::Var(0),
+            Instruction::Drop,
+            Instruction::Mul,
+        ];
+        for result in paths(&shared, Semantics::Checked) {
+            assert_eq!(result, Err(EvalError::NonFinite { index: 2 }));
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::instructions::Instruction;
+
+// Instructions are stored in their `Display` form, e.g. `"Lit(2.5)"`, which also covers custom
+// operators by name. Deserializing a custom operator fails, as there is no registry to resolve its
+// name, use `Instruction::parse_with` on the string instead.
+impl Serialize for Instruction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Instruction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Program, SearchResult};
+
+    #[test]
+    fn test_json_round_trip() {
+        let result = SearchResult {
+            program: Program::create(&[
+                Instruction::Var(0),
+                Instruction::Const(0),
+                Instruction::Mul,
+                Instruction::Lit(0.1),
+                Instruction::Add,
+            ]),
+            consts: vec![2.5],
+            score: -0.25,
+        };
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            json,
+            r#"{"program":{"instructions":["Var(0)","Const(0)","Mul","Lit(0.1)","Add"]},"consts":[2.5],"score":-0.25}"#
+        );
+        assert_eq!(serde_json::from_str::<SearchResult>(&json).unwrap(), result);
+
+        let error = serde_json::from_str::<Instruction>(r#""Const(x)""#).unwrap_err();
+        assert!(error.to_string().contains("expected a constant index"));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::{
+    instructions::{EvalError, Instruction, Program, STACKSIZE},
+    scalar::Scalar,
+    semantics::Semantics,
+};
+
+/// Number of rows processed together by `Program::evaluate_batch_simd`, small enough for the
+/// block's stack to stay in the L1 cache
+pub const LANES: usize = 256;
+
+type Lane<T> = [T; LANES];
+
+// Applies `$body` to every row of the lanes. The operator is matched once outside of the loop, so
+// every loop body is a single operation the compiler can vectorize.
+macro_rules! lanewise {
+    ($a:ident, |$x:ident| $body:expr) => {
+        for $x in $a.iter_mut() {
+            *$x = $body;
+        }
+    };
+    ($a:ident, $b:ident, |$x:ident, $y:ident| $body:expr) => {
+        for ($x, $y) in $a.iter_mut().zip($b.iter()) {
+            *$x = $body;
+        }
+    };
+}
+
+fn apply_binary<T: Scalar>(
+    inst: &Instruction,
+    semantics: Semantics,
+    a: &mut Lane<T>,
+    b: &Lane<T>,
+) {
+    let protected = semantics == Semantics::Protected;
+    match inst {
+        Instruction::Add => lanewise!(a, b, |x, y| *x + *y),
+        Instruction::Sub => lanewise!(a, b, |x, y| *x - *y),
+        Instruction::Mul => lanewise!(a, b, |x, y| *x * *y),
+        Instruction::Div if protected => {
+            lanewise!(a, b, |x, y| if *y == T::ZERO { T::ONE } else { *x / *y })
+        }
+        Instruction::Div => lanewise!(a, b, |x, y| *x / *y),
+        Instruction::Lt => lanewise!(a, b, |x, y| if *x < *y { T::ONE } else { T::ZERO }),
+        Instruction::Gt => lanewise!(a, b, |x, y| if *x > *y { T::ONE } else { T::ZERO }),
+        // Calls into libm per row anyway
+        Instruction::Pow => lanewise!(a, b, |x, y| inst.apply_binary(semantics, *x, *y)),
+        _ => unreachable!("{:?} is not a binary operator", inst),
+    }
+}
+
+fn apply_unary<T: Scalar>(inst: &Instruction, semantics: Semantics, v: &mut Lane<T>) {
+    let protected = semantics == Semantics::Protected;
+    match inst {
+        Instruction::Neg => lanewise!(v, |x| -*x),
+        Instruction::Abs => lanewise!(v, |x| x.abs()),
+        Instruction::Sqrt if protected => lanewise!(v, |x| x.abs().sqrt()),
+        Instruction::Sqrt => lanewise!(v, |x| x.sqrt()),
+        // Calls into libm per row anyway
+        Instruction::Exp
+        | Instruction::Log
+        | Instruction::Sin
+        | Instruction::Cos
+        | Instruction::Tan => lanewise!(v, |x| inst.apply_unary(semantics, *x)),
+        _ => unreachable!("{:?} is not a unary operator", inst),
+    }
+}
+
+/// Checks the rows of a lane that hold data, the padding of the last block is ignored
+fn check<T: Scalar>(semantics: Semantics, lane: &Lane<T>, rows: usize) -> Option<()> {
+    if semantics == Semantics::Checked && !lane[..rows].iter().all(|v| v.is_finite()) {
+        None
+    } else {
+        Some(())
+    }
+}
+
+impl Program {
+    /// Same as `Program::evaluate_batch`, but evaluates the rows in blocks of `LANES` fixed-size
+    /// arrays, so the arithmetic compiles down to SIMD instructions and intermediate values stay
+    /// in cache instead of being written to full-length columns. See `benches/evaluate.rs`.
+    pub fn evaluate_batch_simd<T: Scalar>(
+        &self,
+        consts: &[T],
+        columns: &[&[T]],
+    ) -> Result<Vec<T>, EvalError> {
+        self.evaluate_batch_simd_with(Semantics::default(), consts, columns)
+    }
+
+    /// Same as `Program::evaluate_batch_with`, evaluated in blocks of `LANES` rows
+    pub fn evaluate_batch_simd_with<T: Scalar>(
+        &self,
+        semantics: Semantics,
+        consts: &[T],
+        columns: &[&[T]],
+    ) -> Result<Vec<T>, EvalError> {
+        let rows = columns.first().map(|c| c.len()).unwrap_or(1);
+        if let Some(column) = columns.iter().position(|c| c.len() != rows) {
+            return Err(EvalError::ColumnLengthMismatch { column });
+        }
+        let mut results = Vec::with_capacity(rows);
+        let mut stack: Vec<Lane<T>> = Vec::new();
+        let mut args = Vec::new();
+        // Without rows, an empty block still reports the errors of the program itself
+        for start in (0..rows.max(1)).step_by(LANES) {
+            let end = (start + LANES).min(rows);
+            stack.clear();
+            for (index, inst) in self.instructions.iter().enumerate() {
+                let underflow = EvalError::StackUnderflow { index };
+                let non_finite = EvalError::NonFinite { index };
+                match inst {
+                    Instruction::Add
+                    | Instruction::Sub
+                    | Instruction::Mul
+                    | Instruction::Div
+                    | Instruction::Pow
+                    | Instruction::Lt
+                    | Instruction::Gt => {
+                        let b = stack.pop().ok_or(underflow)?;
+                        let a = stack.last_mut().ok_or(underflow)?;
+                        apply_binary(inst, semantics, a, &b);
+                        check(semantics, a, end - start).ok_or(non_finite)?;
+                    }
+                    Instruction::Exp
+                    | Instruction::Log
+                    | Instruction::Sin
+                    | Instruction::Cos
+                    | Instruction::Tan
+                    | Instruction::Sqrt
+                    | Instruction::Abs
+                    | Instruction::Neg => {
+                        let v = stack.last_mut().ok_or(underflow)?;
+                        apply_unary(inst, semantics, v);
+                        check(semantics, v, end - start).ok_or(non_finite)?;
+                    }
+                    Instruction::Select => {
+                        let f = stack.pop().ok_or(underflow)?;
+                        let t = stack.pop().ok_or(underflow)?;
+                        let c = stack.last_mut().ok_or(underflow)?;
+                        for i in 0..LANES {
+                            c[i] = Instruction::apply_select(c[i], t[i], f[i]);
+                        }
+                    }
+                    Instruction::Dup => {
+                        let v = *stack.last().ok_or(underflow)?;
+                        stack.push(v);
+                    }
+                    Instruction::Swap => {
+                        let len = stack.len();
+                        if len < 2 {
+                            return Err(underflow);
+                        }
+                        stack.swap(len - 1, len - 2);
+                    }
+                    Instruction::Drop => {
+                        stack.pop().ok_or(underflow)?;
+                    }
+                    Instruction::Over => {
+                        let len = stack.len();
+                        if len < 2 {
+                            return Err(underflow);
+                        }
+                        stack.push(stack[len - 2]);
+                    }
+                    Instruction::Rot => {
+                        let len = stack.len();
+                        if len < 3 {
+                            return Err(underflow);
+                        }
+                        stack[len - 3..].rotate_left(1);
+                    }
+                    Instruction::Const(idx) => {
+                        let v = consts.get(*idx as usize).ok_or(EvalError::ConstOutOfRange {
+                            index,
+                            const_index: *idx,
+                        })?;
+                        stack.push([*v; LANES]);
+                    }
+                    Instruction::Var(idx) => {
+                        let column = columns.get(*idx as usize).ok_or(EvalError::VarOutOfRange {
+                            index,
+                            var_index: *idx,
+                        })?;
+                        let mut lane = [T::ZERO; LANES];
+                        lane[..end - start].copy_from_slice(&column[start..end]);
+                        stack.push(lane);
+                    }
+                    Instruction::Lit(v) => {
+                        stack.push([T::from_f32(*v); LANES]);
+                    }
+                    Instruction::Custom(op) => {
+                        let arity = op.arity();
+                        if stack.len() < arity {
+                            return Err(underflow);
+                        }
+                        let inputs = stack.split_off(stack.len() - arity);
+                        let mut result = [T::ZERO; LANES];
+                        for (i, r) in result[..end - start].iter_mut().enumerate() {
+                            args.clear();
+                            args.extend(inputs.iter().map(|lane| lane[i]));
+                            *r = T::eval_operator(*op, &args);
+                        }
+                        check(semantics, &result, end - start).ok_or(non_finite)?;
+                        stack.push(result);
+                    }
+                }
+                if stack.len() > STACKSIZE {
+                    return Err(EvalError::StackOverflow { index });
+                }
+            }
+            let result = stack.pop().ok_or(EvalError::EmptyResult)?;
+            results.extend_from_slice(&result[..end - start]);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EvalError, Instruction, Program, LANES};
+
+    #[test]
+    fn test_simd_matches_batch_evaluation() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Over,
+            Instruction::Div,
+            Instruction::Lit(1.0),
+            Instruction::Rot,
+            Instruction::Log,
+            Instruction::Pow,
+            Instruction::Swap,
+            Instruction::Var(1),
+            Instruction::Lt,
+            Instruction::Var(0),
+            Instruction::Rot,
+            Instruction::Select,
+            Instruction::Const(0),
+            Instruction::Mul,
+        ]);
+        let consts: [f32; 1] = [0.5];
+        // The last block is only partially filled
+        let rows = 2 * LANES + 19;
+        let x = (0..rows).map(|i| i as f32 * 0.1 - 9.0).collect::<Vec<_>>();
+        let y = (0..rows)
+            .map(|i| (i as f32 * 0.7).sin())
+            .collect::<Vec<_>>();
+        let simd = program.evaluate_batch_simd(&consts, &[&x, &y]).unwrap();
+        let batch = program.evaluate_batch(&consts, &[&x, &y]).unwrap();
+        assert_eq!(simd.len(), x.len());
+        assert!(simd
+            .iter()
+            .zip(batch.iter())
+            .all(|(s, b)| s.to_bits() == b.to_bits()));
+    }
+
+    #[test]
+    fn test_simd_without_rows() {
+        let empty: &[f32] = &[];
+        let add = Program::create(&[Instruction::Add]);
+        assert_eq!(
+            add.evaluate_batch_simd::<f32>(&[], &[empty]),
+            Err(EvalError::StackUnderflow { index: 0 })
+        );
+        assert_eq!(
+            add.evaluate_batch_simd(&[], &[empty]),
+            add.evaluate_batch(&[], &[empty])
+        );
+        let program =
+            Program::create(&[Instruction::Var(0), Instruction::Const(0), Instruction::Add]);
+        assert_eq!(
+            program.evaluate_batch_simd::<f32>(&[], &[empty]),
+            Err(EvalError::ConstOutOfRange {
+                index: 1,
+                const_index: 0
+            })
+        );
+        assert_eq!(program.evaluate_batch_simd(&[1.0], &[empty]), Ok(vec![]));
+    }
+}
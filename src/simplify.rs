@@ -0,0 +1,332 @@
+use crate::{
+    expr::Expr,
+    instructions::{Instruction, Program},
+    semantics::Semantics,
+};
+
+/// Sum of terms `coefficient * expr`, with all purely numeric terms folded into `constant`
+struct Sum {
+    terms: Vec<(f32, Expr)>,
+    constant: f32,
+}
+
+impl Sum {
+    fn add(&mut self, coefficient: f32, expr: Expr, consts: &[f32]) {
+        if let Some(v) = number(&expr, consts) {
+            self.constant += coefficient * v;
+            return;
+        }
+        match expr {
+            Expr::Op(Instruction::Add, operands) => {
+                for operand in operands {
+                    self.add(coefficient, operand, consts);
+                }
+            }
+            Expr::Op(Instruction::Sub, mut operands) => {
+                let b = operands.pop().unwrap();
+                let a = operands.pop().unwrap();
+                self.add(coefficient, a, consts);
+                self.add(-coefficient, b, consts);
+            }
+            Expr::Op(Instruction::Neg, mut operands) => {
+                self.add(-coefficient, operands.pop().unwrap(), consts)
+            }
+            Expr::Op(Instruction::Mul, mut operands) => {
+                let b = operands.pop().unwrap();
+                let a = operands.pop().unwrap();
+                match (number(&a, consts), number(&b, consts)) {
+                    (Some(c), _) => self.add(coefficient * c, b, consts),
+                    (_, Some(c)) => self.add(coefficient * c, a, consts),
+                    _ => self.push(coefficient, Expr::Op(Instruction::Mul, vec![a, b])),
+                }
+            }
+            expr => self.push(coefficient, expr),
+        }
+    }
+
+    /// Adds a term, collecting it with an existing like term
+    fn push(&mut self, coefficient: f32, expr: Expr) {
+        match self.terms.iter_mut().find(|(_, e)| *e == expr) {
+            Some((c, _)) => *c += coefficient,
+            None => self.terms.push((coefficient, expr)),
+        }
+    }
+
+    fn into_expr(self) -> Expr {
+        let mut result: Option<Expr> = None;
+        for (coefficient, expr) in self.terms.into_iter().filter(|(c, _)| *c != 0.0) {
+            let magnitude = |c: f32, e: Expr| {
+                if c == 1.0 {
+                    e
+                } else {
+                    Expr::Op(Instruction::Mul, vec![Expr::Lit(c), e])
+                }
+            };
+            result = Some(match result {
+                None if coefficient == -1.0 => Expr::Op(Instruction::Neg, vec![expr]),
+                None => magnitude(coefficient, expr),
+                Some(sum) if coefficient < 0.0 => Expr::Op(
+                    Instruction::Sub,
+                    vec![sum, magnitude(-coefficient, expr)],
+                ),
+                Some(sum) => Expr::Op(Instruction::Add, vec![sum, magnitude(coefficient, expr)]),
+            });
+        }
+        match result {
+            None => Expr::Lit(self.constant),
+            Some(sum) if self.constant == 0.0 => sum,
+            Some(sum) if self.constant < 0.0 => {
+                Expr::Op(Instruction::Sub, vec![sum, Expr::Lit(-self.constant)])
+            }
+            Some(sum) => Expr::Op(Instruction::Add, vec![sum, Expr::Lit(self.constant)]),
+        }
+    }
+}
+
+/// Value of a leaf that is a number
+fn number(expr: &Expr, consts: &[f32]) -> Option<f32> {
+    match expr {
+        Expr::Lit(v) => Some(*v),
+        Expr::Const(idx) => consts.get(*idx as usize).copied(),
+        _ => None,
+    }
+}
+
+fn is(expr: &Expr, consts: &[f32], value: f32) -> bool {
+    number(expr, consts) == Some(value)
+}
+
+/// Splits a factor into its numeric coefficient and the remaining non-numeric factor, looking
+/// into products with a numeric operand
+fn coefficient(expr: Expr, consts: &[f32]) -> (f32, Option<Expr>) {
+    if let Some(v) = number(&expr, consts) {
+        return (v, None);
+    }
+    match expr {
+        Expr::Op(Instruction::Mul, mut operands) => {
+            match (number(&operands[0], consts), number(&operands[1], consts)) {
+                (Some(c), _) => (c, operands.pop()),
+                (_, Some(c)) => (c, Some(operands.swap_remove(0))),
+                _ => (1.0, Some(Expr::Op(Instruction::Mul, operands))),
+            }
+        }
+        expr => (1.0, Some(expr)),
+    }
+}
+
+/// Evaluates an operator on numbers with the default semantics of `Program::run`
+fn fold(inst: Instruction, args: &[f32]) -> f32 {
+    let semantics = Semantics::default();
+    match inst {
+        Instruction::Select => Instruction::apply_select(args[0], args[1], args[2]),
+        Instruction::Custom(op) => op.eval(args),
+        _ if args.len() == 2 => inst.apply_binary(semantics, args[0], args[1]),
+        _ => inst.apply_unary(semantics, args[0]),
+    }
+}
+
+fn simplify(expr: Expr, consts: &[f32]) -> Expr {
+    let Expr::Op(inst, operands) = expr else {
+        return expr;
+    };
+    let mut operands = operands
+        .into_iter()
+        .map(|operand| simplify(operand, consts))
+        .collect::<Vec<_>>();
+    if let Some(args) = operands
+        .iter()
+        .map(|operand| number(operand, consts))
+        .collect::<Option<Vec<_>>>()
+    {
+        let v = fold(inst, &args);
+        if v.is_finite() {
+            return Expr::Lit(v);
+        }
+    }
+    match inst {
+        Instruction::Add | Instruction::Sub | Instruction::Neg => {
+            let mut sum = Sum {
+                terms: Vec::new(),
+                constant: 0.0,
+            };
+            sum.add(1.0, Expr::Op(inst, operands), consts);
+            sum.into_expr()
+        }
+        Instruction::Mul if is(&operands[0], consts, 0.0) || is(&operands[1], consts, 0.0) => {
+            Expr::Lit(0.0)
+        }
+        Instruction::Mul if is(&operands[0], consts, 1.0) => operands.pop().unwrap(),
+        Instruction::Mul if is(&operands[1], consts, 1.0) => operands.swap_remove(0),
+        Instruction::Mul => {
+            let b = operands.pop().unwrap();
+            let a = operands.pop().unwrap();
+            let (ca, a) = coefficient(a, consts);
+            let (cb, b) = coefficient(b, consts);
+            let product = match (a, b) {
+                (Some(a), Some(b)) => Expr::Op(Instruction::Mul, vec![a, b]),
+                (Some(e), None) | (None, Some(e)) => e,
+                (None, None) => unreachable!("numeric products are folded"),
+            };
+            if ca * cb == 1.0 {
+                product
+            } else {
+                Expr::Op(Instruction::Mul, vec![Expr::Lit(ca * cb), product])
+            }
+        }
+        Instruction::Div if is(&operands[1], consts, 1.0) => operands.swap_remove(0),
+        // Protected division of zero by zero is 1, so x / x is 1 everywhere
+        Instruction::Div if operands[0] == operands[1] => Expr::Lit(1.0),
+        Instruction::Pow if is(&operands[1], consts, 1.0) => operands.swap_remove(0),
+        Instruction::Pow if is(&operands[1], consts, 0.0) => Expr::Lit(1.0),
+        Instruction::Exp | Instruction::Log => match operands.pop().unwrap() {
+            // exp and ln cancel within the domain of ln
+            Expr::Op(inner, mut inner_operands)
+                if (inst == Instruction::Exp && inner == Instruction::Log)
+                    || (inst == Instruction::Log && inner == Instruction::Exp) =>
+            {
+                inner_operands.pop().unwrap()
+            }
+            operand => Expr::Op(inst, vec![operand]),
+        },
+        Instruction::Abs => match operands.pop().unwrap() {
+            Expr::Op(Instruction::Abs, inner) => Expr::Op(Instruction::Abs, inner),
+            operand => Expr::Op(inst, vec![operand]),
+        },
+        Instruction::Select => match number(&operands[0], consts) {
+            Some(c) if c != 0.0 => operands.swap_remove(1),
+            Some(_) => operands.pop().unwrap(),
+            None if operands[1] == operands[2] => operands.pop().unwrap(),
+            None => Expr::Op(inst, operands),
+        },
+        _ => Expr::Op(inst, operands),
+    }
+}
+
+impl Program {
+    /// Algebraically simplifies the value the program leaves on top of its stack: folds
+    /// constants (using the values in `consts`), removes identities like `x * 1`, `x + 0`, `x - x`
+    /// and `exp(ln(x))`, and collects like terms such as `2 * x + x`.
+    ///
+    /// Rewrites assume operator arguments lie within the operator's mathematical domain, e.g.
+    /// `exp(ln(x))` becomes `x` although the protected `ln(|x|)` differs for negative `x`.
+    /// The simplified program is only returned if it is shorter, otherwise a copy of the program
+    /// is returned. Returns `None` if the program does not leave a result on its stack.
+    pub fn simplify(&self, consts: &[f32]) -> Option<Program> {
+        let mut expr = Expr::from_program(self)?;
+        // Collecting terms can expose new identities, so repeat until nothing changes
+        for _ in 0..8 {
+            let simplified = simplify(expr.clone(), consts);
+            if simplified == expr {
+                break;
+            }
+            expr = simplified;
+        }
+        let simplified = expr.to_program_unchecked();
+        if simplified.len() < self.len() {
+            Some(simplified)
+        } else {
+            Some(self.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Instruction, Program};
+
+    fn simplified(insts: &[Instruction], consts: &[f32]) -> String {
+        Program::create(insts)
+            .simplify(consts)
+            .unwrap()
+            .render_pretty(consts)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_constant_folding_and_identities() {
+        // (c_0 + c_1) * v_0 + 0
+        let insts = [
+            Instruction::Const(0),
+            Instruction::Const(1),
+            Instruction::Add,
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Lit(0.0),
+            Instruction::Add,
+        ];
+        assert_eq!(simplified(&insts, &[1.0, 2.0]), "(3 * v_0)");
+        assert_eq!(simplified(&insts, &[-1.0, 2.0]), "v_0");
+
+        // exp(ln(v_0 - v_0 + v_1))
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Var(0),
+            Instruction::Sub,
+            Instruction::Var(1),
+            Instruction::Add,
+            Instruction::Log,
+            Instruction::Exp,
+        ];
+        assert_eq!(simplified(&insts, &[]), "v_1");
+    }
+
+    #[test]
+    fn test_like_term_collection() {
+        // 2 * v_0 + v_1 + v_0 * 3 - v_1
+        let insts = [
+            Instruction::Lit(2.0),
+            Instruction::Var(0),
+            Instruction::Mul,
+            Instruction::Var(1),
+            Instruction::Add,
+            Instruction::Var(0),
+            Instruction::Lit(3.0),
+            Instruction::Mul,
+            Instruction::Add,
+            Instruction::Var(1),
+            Instruction::Sub,
+        ];
+        assert_eq!(simplified(&insts, &[]), "(5 * v_0)");
+
+        // v_0 * v_1 + v_0 * v_1 - 1 - 1
+        let insts = [
+            Instruction::Var(0),
+            Instruction::Var(1),
+            Instruction::Mul,
+            Instruction::Dup,
+            Instruction::Add,
+            Instruction::Lit(1.0),
+            Instruction::Sub,
+            Instruction::Lit(1.0),
+            Instruction::Sub,
+        ];
+        assert_eq!(simplified(&insts, &[]), "((2 * (v_0 * v_1)) - 2)");
+    }
+
+    #[test]
+    fn test_simplified_program_is_equivalent() {
+        let program = Program::create(&[
+            Instruction::Var(0),
+            Instruction::Const(0),
+            Instruction::Mul,
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Lit(1.0),
+            Instruction::Pow,
+            Instruction::Add,
+            Instruction::Var(0),
+            Instruction::Sin,
+            Instruction::Sub,
+            Instruction::Const(1),
+            Instruction::Div,
+        ]);
+        let consts = [4.0, 1.0];
+        let simplified = program.simplify(&consts).unwrap();
+        assert!(simplified.len() < program.len());
+        for x in [-2.0f32, 0.5, 3.0] {
+            let expected = program.evaluate_to_result(&consts, &[x]).unwrap();
+            let result = simplified.evaluate_to_result(&consts, &[x]).unwrap();
+            assert!((expected - result).abs() < 1e-5);
+        }
+    }
+}